@@ -13,6 +13,28 @@ fn main() -> Result<(), schema::SchemaError> {
   let op = &args[1];
   let filename = &args[2];
 
+  if op == "fmt" {
+    let query = fs::read_to_string(filename)?;
+    let query = match parser::process_query(&query) {
+      Ok(query) => query,
+      Err(err) => {
+        eprintln!("{}", err);
+        std::process::exit(1);
+      }
+    };
+    for (idx, statement) in query.iter().enumerate() {
+      // runs of the same kind of statement stay together, like the inserts
+      // in testing.sql
+      let same_kind =
+        idx > 0 && std::mem::discriminant(statement) == std::mem::discriminant(&query[idx - 1]);
+      if idx > 0 && !same_kind {
+        println!();
+      }
+      println!("{:#};", statement);
+    }
+    return Ok(());
+  }
+
   if op == "create" || op == "init" {
     let file = fs::OpenOptions::new()
       .read(true)
@@ -38,37 +60,48 @@ fn main() -> Result<(), schema::SchemaError> {
     }
 
     let query = fs::read_to_string(&args[3])?;
-    let query = parser::process_query(&query).expect("Invalid SQL");
+    let query = match parser::process_query(&query) {
+      Ok(query) => query,
+      Err(err) => {
+        eprintln!("{}", err);
+        std::process::exit(1);
+      }
+    };
 
     for statement in query.into_iter() {
       let mut table = prettytable::Table::new();
-      match database.process_statement(&statement).unwrap() {
-        Some(mut result_iter) => {
-          let schema = result_iter.schema();
+      let result_iter = database
+        .process_statement(&statement)
+        .unwrap_or_else(|err| exit_with_error(err));
+      if let Some(mut result_iter) = result_iter {
+        let schema = result_iter.schema();
 
-          {
-            let schema = result_iter.schema();
-            let mut cells = vec![];
-            for field in schema.iter() {
-              match field.name() {
-                Some(name) => cells.push(prettytable::Cell::new(name)),
-                None => cells.push(prettytable::Cell::new("<unnamed>")),
-              };
-            }
-            table.add_row(prettytable::Row::new(cells));
-          };
-          while let Some(row) = result_iter.next_row(&mut database).unwrap() {
-            let row = row.into_cells(&schema).unwrap();
-            table.add_row(prettytable::Row::new(
-              row
-                .into_iter()
-                .map(|cell| prettytable::Cell::new(&format!("{}", cell.as_rowcell())))
-                .collect(),
-            ));
+        {
+          let schema = result_iter.schema();
+          let mut cells = vec![];
+          for field in schema.iter() {
+            match field.name() {
+              Some(name) => cells.push(prettytable::Cell::new(name)),
+              None => cells.push(prettytable::Cell::new("<unnamed>")),
+            };
           }
-          table.printstd();
+          table.add_row(prettytable::Row::new(cells));
+        };
+        while let Some(row) = result_iter
+          .next_row(&mut database)
+          .unwrap_or_else(|err| exit_with_error(err))
+        {
+          let row = row
+            .into_cells(&schema)
+            .unwrap_or_else(|err| exit_with_error(err));
+          table.add_row(prettytable::Row::new(
+            row
+              .into_iter()
+              .map(|cell| prettytable::Cell::new(&format!("{}", cell.as_rowcell())))
+              .collect(),
+          ));
         }
-        None => {}
+        table.printstd();
       }
     }
   } else if op == "repl" {
@@ -88,6 +121,7 @@ fn main() -> Result<(), schema::SchemaError> {
         println!("{:?}", row);
       }) {
         Ok(result) => println!("{:?}", result),
+        Err(db::DatabaseQueryError::AstError(err)) => println!("{}", err),
         Err(err) => println!("{:?}", err),
       };
     }
@@ -102,3 +136,10 @@ fn main() -> Result<(), schema::SchemaError> {
 
   Ok(())
 }
+
+/// Statements that fail while they're running are reported the same way
+/// as ones that don't parse
+fn exit_with_error(err: impl std::fmt::Debug) -> ! {
+  eprintln!("{:?}", err);
+  std::process::exit(1);
+}
@@ -17,6 +17,7 @@ pub struct BlockMeta {
   /// Reasons why this wouldn't exist:
   /// - This type of block never has additional blocks (e.g. the Root block)
   /// - This is the last block in the linked list
+  ///
   /// If this doesn't exist, it is all zeros.
   next_block: Option<u64>,
 
@@ -175,13 +176,13 @@ impl<'a> io::Read for BlockDiskView<'a> {
     );
     self.end_of_block()?;
 
-    for i in 0..buf.len() {
+    for (i, byte) in buf.iter_mut().enumerate() {
       let offset = self.current_offset as usize;
       if self.is_at_end_of_block() {
         log::debug!("-> Reached end of block early. Read {}", i);
         return Ok(i);
       }
-      buf[i] = self.block.data[offset];
+      *byte = self.block.data[offset];
       self.current_offset += 1;
     }
     Ok(buf.len())
@@ -208,7 +209,7 @@ impl<'a> io::Write for BlockDiskView<'a> {
 
     let bytes_written = slice_write(&mut self.current_offset, self.block.data.as_mut(), buf)?;
     self.block.meta.size = std::cmp::max(self.block.meta.size, self.current_offset);
-    return Ok(bytes_written);
+    Ok(bytes_written)
   }
   fn flush(&mut self) -> io::Result<()> {
     Ok(())
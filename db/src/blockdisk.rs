@@ -62,7 +62,7 @@ impl<'a, D: BlockAllocator> BlockDisk<'a, D> {
     let current_block = self.blocks.last_mut().unwrap();
     let next_block = current_block.meta().next_block();
     match next_block {
-      Some(_) => return Ok(()),
+      Some(_) => Ok(()),
       None => {
         let next_block = self.disk.allocate_block()?;
         current_block.set_next_block(Some(next_block.meta().offset()));
@@ -298,5 +298,4 @@ mod tests {
     assert_eq!(12, blockdisk.read_u64::<BigEndian>()?);
     Ok(())
   }
-
 }
@@ -1,10 +1,13 @@
-use crate::table::{Table, TableError};
+use crate::expr::{common_kind, Expression, OuterRow, Subquery};
+use crate::table::{table_fields, Table, TableError, TableField};
 use crate::{Block, BlockDisk};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use log::debug;
-use schema::{OnDiskSchema, Row, Schema};
+use schema::{Field, OnDiskSchema, Row, Schema};
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
 use std::io::{self, Read, Seek, Write};
+use std::rc::Rc;
 
 /// Convenience trait for read + write + seek
 pub trait Disk: io::Read + io::Write + io::Seek {}
@@ -14,6 +17,34 @@ impl<T: io::Read + io::Write + io::Seek> Disk for T {}
 pub struct Database<T: Disk> {
   disk: T,
   meta: DatabaseMeta,
+  /// The values bound to the parameters of the statement that's being
+  /// run, by number (starting from 1)
+  parameters: Vec<schema::OwnedRowCell>,
+}
+
+/// A query that's been parsed once, so that it can be run any number of
+/// times with different values for its parameters
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+  query: parser::PreparedQuery,
+}
+
+impl PreparedStatement {
+  /// How many values have to be bound to run the statement
+  pub fn parameter_count(&self) -> usize {
+    self.query.parameters.len()
+  }
+
+  /// The number of the parameter called `name` (e.g. `:id`), which is
+  /// where its value goes when the statement is run
+  pub fn parameter_index(&self, name: &str) -> Option<usize> {
+    self
+      .query
+      .parameters
+      .iter()
+      .position(|parameter| parameter.as_deref() == Some(name))
+      .map(|idx| idx + 1)
+  }
 }
 
 /// Basically a structure that holds all the information in the root block
@@ -31,6 +62,13 @@ struct DatabaseMeta {
   /// The offset of the schema block. Usually this will be
   /// the first block after the root block but it's configurable
   schema_block_offset: u64,
+  /// The offset of the first block in the free list, or 0 if there
+  /// aren't any free blocks. Free blocks are linked together through
+  /// their `next_block`, just like the blocks of a table.
+  ///
+  /// Databases from before this existed have zeros here, which is an
+  /// empty free list.
+  free_block_offset: u64,
 }
 
 impl DatabaseMeta {
@@ -43,6 +81,7 @@ impl DatabaseMeta {
       block_size_exp,
       num_allocated_blocks: 2, // 1 for the root block, 1 for the schema block
       schema_block_offset: 2u64.pow(block_size_exp as u32),
+      free_block_offset: 0,
     }
   }
 
@@ -56,6 +95,7 @@ impl DatabaseMeta {
     disk.write_u8(self.block_size_exp)?;
     disk.write_u64::<BigEndian>(self.num_allocated_blocks)?;
     disk.write_u64::<BigEndian>(self.schema_block_offset)?;
+    disk.write_u64::<BigEndian>(self.free_block_offset)?;
     Ok(())
   }
 
@@ -65,11 +105,13 @@ impl DatabaseMeta {
     let block_size_exp = disk.read_u8()?;
     let num_allocated_blocks = disk.read_u64::<BigEndian>()?;
     let schema_block_offset = disk.read_u64::<BigEndian>()?;
+    let free_block_offset = disk.read_u64::<BigEndian>()?;
     Ok(DatabaseMeta {
       version,
       block_size_exp,
       num_allocated_blocks,
       schema_block_offset,
+      free_block_offset,
     })
   }
 }
@@ -119,34 +161,75 @@ impl From<TableError> for DatabaseError {
 }
 
 #[derive(Debug)]
-pub enum DatabaseQueryError<'a> {
+pub enum DatabaseQueryError {
   InternalError(DatabaseError),
-  AstError(parser::AstError<'a>),
+  AstError(parser::AstError),
 }
 
-impl<'a> From<DatabaseError> for DatabaseQueryError<'a> {
+impl From<DatabaseError> for DatabaseQueryError {
   fn from(err: DatabaseError) -> Self {
     DatabaseQueryError::InternalError(err)
   }
 }
-impl<'a> From<parser::AstError<'a>> for DatabaseQueryError<'a> {
-  fn from(err: parser::AstError<'a>) -> Self {
+impl From<parser::AstError> for DatabaseQueryError {
+  fn from(err: parser::AstError) -> Self {
     DatabaseQueryError::AstError(err)
   }
 }
 
 impl<T: Disk> Database<T> {
-  pub fn execute_query<'a, 'disk, F>(
-    &'disk mut self,
-    query: &'a str,
-    mut f: F,
-  ) -> Result<(), DatabaseQueryError<'a>>
+  pub fn execute_query<F>(&mut self, query: &str, mut f: F) -> Result<(), DatabaseQueryError>
   where
-    F: FnMut(Option<Vec<schema::OwnedRowCell>>) -> (),
+    F: FnMut(Option<Vec<schema::OwnedRowCell>>),
   {
     let ast = parser::process_query(query)?;
-    for statement in ast.into_iter() {
-      match self.process_statement(&statement)? {
+    self.execute_statements(&ast, &mut f)?;
+    Ok(())
+  }
+
+  /// Parses `query` without running it. Its parameters (`?`, `?NNN` and
+  /// `:name`) get values each time it's run with `execute_prepared`.
+  pub fn prepare(&self, query: &str) -> Result<PreparedStatement, DatabaseQueryError> {
+    Ok(PreparedStatement {
+      query: parser::prepare_query(query)?,
+    })
+  }
+
+  /// Runs a prepared statement, with `parameters` bound to its parameters
+  /// in order. Named parameters are bound by number too, which
+  /// `PreparedStatement::parameter_index` looks up.
+  pub fn execute_prepared<F>(
+    &mut self,
+    statement: &PreparedStatement,
+    parameters: &[schema::OwnedRowCell],
+    mut f: F,
+  ) -> Result<(), DatabaseQueryError>
+  where
+    F: FnMut(Option<Vec<schema::OwnedRowCell>>),
+  {
+    if parameters.len() != statement.parameter_count() {
+      return Err(
+        DatabaseError::Other(format!(
+          "Error: Statement has {} parameters, but {} values were bound",
+          statement.parameter_count(),
+          parameters.len()
+        ))
+        .into(),
+      );
+    }
+    self.parameters = parameters.to_vec();
+    let result = self.execute_statements(&statement.query.statements, &mut f);
+    self.parameters.clear();
+    Ok(result?)
+  }
+
+  fn execute_statements(
+    &mut self,
+    statements: &[parser::Statement],
+    f: &mut dyn FnMut(Option<Vec<schema::OwnedRowCell>>),
+  ) -> Result<(), DatabaseError> {
+    for statement in statements.iter() {
+      match self.process_statement(statement)? {
         Some(mut result_iter) => {
           let schema = result_iter.schema();
           while let Some(row) = result_iter
@@ -161,15 +244,18 @@ impl<T: Disk> Database<T> {
     }
     Ok(())
   }
-  pub fn process_statement<'a>(
+  pub fn process_statement(
     &mut self,
-    ast: &parser::Statement<'a>,
+    ast: &parser::Statement,
   ) -> Result<Option<Box<dyn Table>>, DatabaseError> {
     use parser::Statement;
     match ast {
       Statement::CreateTable(create_table_statement) => {
         // does this table already exist?
-        if let Ok(_) = self.get_table(create_table_statement.table_name.text()) {
+        if self
+          .get_table(create_table_statement.table_name.text())
+          .is_ok()
+        {
           return Err(DatabaseError::Other(format!(
             "Could not create table {}: table with the same name already exists",
             create_table_statement.table_name.text()
@@ -187,7 +273,7 @@ impl<T: Disk> Database<T> {
           schema_fields,
         );
         self.create_table(schema)?;
-        return Ok(None);
+        Ok(None)
       }
       Statement::Insert(insert_statement) => {
         use parser::InsertStatementValues as Values;
@@ -214,122 +300,831 @@ impl<T: Disk> Database<T> {
 
         match &insert_statement.values {
           Values::SingleRow(row) => {
-            self.insert_ast_row(schema, &row, &mapping)?;
+            self.insert_ast_row(schema, row, &mapping)?;
             Ok(None)
           }
           Values::MultipleRows(rows) => {
             for row in rows.iter() {
-              self.insert_ast_row(schema, &row, &mapping)?;
+              self.insert_ast_row(schema, row, &mapping)?;
             }
             Ok(None)
           }
         }
       }
-      Statement::Select(select_statement) => {
-        match &select_statement.table {
-          Some(table) => {
-            use crate::table::TableField;
-            use crate::table::TableFieldLiteral;
-            use parser::{Expr, LiteralValue, ResultColumn};
-            use schema::FieldKind;
-            let table = self.get_table(table.text())?;
-            let mut next_schema = vec![];
-            let mut alias_mapping: BTreeMap<&str, &str> = BTreeMap::new();
-            for column in select_statement.columns.iter() {
-              match column {
-                ResultColumn::Asterisk => {
-                  for field in table.schema().fields().iter() {
-                    next_schema.push(TableField::new(
-                      Some(field.name().to_string()),
-                      field.kind().clone(),
-                      None,
-                    ))
-                  }
-                }
-                ResultColumn::TableAsterisk(_table) => unimplemented!(),
-                ResultColumn::Expr { value, alias } => match value {
-                  Expr::ColumnIdent(column_ident) => {
-                    let schema_column = table.schema().field(column_ident.column.text()).ok_or(
-                      DatabaseError::Other(format!(
-                        "Error: Could not find column {} in table",
-                        column_ident.column
-                      )),
-                    )?;
-                    if let Some(alias) = alias {
-                      alias_mapping.insert(column_ident.column.text(), alias.text());
-                    }
-
-                    next_schema.push(TableField::new(
-                      Some(
-                        alias
-                          .as_ref()
-                          .map(|alias| alias.text())
-                          .unwrap_or(column_ident.column.text())
-                          .to_string(),
-                      ),
-                      schema_column.kind().clone(),
-                      None,
-                    ))
-                  }
-                  Expr::LiteralValue(literal_value) => {
-                    let alias = alias.as_ref().map(|alias| alias.text().to_string());
-                    let value = match literal_value {
-                      LiteralValue::NumericLiteral(num) => TableField::new(
-                        alias,
-                        FieldKind::Number(8),
-                        Some(TableFieldLiteral::Number(*num)),
-                      ),
-                      LiteralValue::StringLiteral(string) => TableField::new(
-                        alias,
-                        FieldKind::Str(string.len() as u64),
-                        Some(TableFieldLiteral::Str(string.to_string())),
-                      ),
-                      LiteralValue::BlobLiteral(blob) => TableField::new(
-                        alias,
-                        FieldKind::Blob(blob.len() as u64),
-                        // TODO :: handle this error but ugh I want to see this work!
-                        Some(TableFieldLiteral::Blob(hex::decode(blob).unwrap())),
-                      ),
-                    };
-                    next_schema.push(value);
-                  }
-                },
-              }
+      Statement::Select(select_statement) => Ok(Some(self.select(select_statement, &[])?)),
+      Statement::Delete(delete_statement) => {
+        self.delete(delete_statement)?;
+        Ok(None)
+      }
+      Statement::Update(update_statement) => {
+        self.update(update_statement)?;
+        Ok(None)
+      }
+      Statement::DropTable(drop_table_statement) => {
+        self.drop_table(drop_table_statement)?;
+        Ok(None)
+      }
+      Statement::AlterTable(alter_table_statement) => {
+        self.alter_table(alter_table_statement)?;
+        Ok(None)
+      }
+    }
+  }
+
+  /// Deletes every row that matches the where clause, by marking them with a tombstone
+  fn delete(&mut self, delete_statement: &parser::DeleteStatement) -> Result<(), DatabaseError> {
+    let table = self.get_table(delete_statement.table.text())?;
+    let fields = table_fields(&table);
+    let rows = self.matching_rows(&table, &fields, delete_statement.where_clause.as_ref())?;
+    for (index, _) in rows {
+      let mut data_blockdisk = BlockDisk::new(self, table.data_block_offset())?;
+      unsafe { Row::delete_row(index, &mut data_blockdisk, table.schema())? };
+    }
+    Ok(())
+  }
+
+  /// Rewrites every row that matches the where clause in place. Rows are
+  /// fixed width, so the new row always fits where the old one was.
+  fn update(&mut self, update_statement: &parser::UpdateStatement) -> Result<(), DatabaseError> {
+    let table_name = update_statement.table.text();
+    let table = self.get_table(table_name)?;
+    let fields = table_fields(&table);
+
+    let mut assignments = Vec::with_capacity(update_statement.assignments.len());
+    for assignment in update_statement.assignments.iter() {
+      let idx = fields
+        .iter()
+        .position(|field| field.name() == Some(assignment.column.text()))
+        .ok_or_else(|| {
+          DatabaseError::Other(format!(
+            "Could not update {}: Could not find column {} in table",
+            table_name, assignment.column
+          ))
+        })?;
+      let value = self.compile_expr(&assignment.value, &fields, &[], &|_| None)?;
+      assignments.push((idx, value));
+    }
+
+    // every row is checked before any of them are written, so a row that
+    // can't be updated doesn't leave the rows before it half updated
+    let rows = self.matching_rows(&table, &fields, update_statement.where_clause.as_ref())?;
+    let mut updates = Vec::with_capacity(rows.len());
+    for (index, row) in rows {
+      let cells = row.as_cells(&fields)?;
+      let mut next_row: Vec<schema::OwnedRowCell> = cells
+        .iter()
+        .cloned()
+        .map(schema::OwnedRowCell::from)
+        .collect();
+      // every assignment sees the row as it was before the update
+      for (idx, value) in assignments.iter() {
+        next_row[*idx] = value.eval(&cells, self)?;
+      }
+
+      let mut valid_row = Vec::with_capacity(next_row.len());
+      for (cell, field) in next_row.into_iter().zip(table.schema().fields().iter()) {
+        if cell == schema::OwnedRowCell::Null {
+          return Err(DatabaseError::Other(format!(
+            "Could not update {}: Column {} can not hold NULL",
+            table_name,
+            field.name()
+          )));
+        }
+        match cell.coerce_to(field) {
+          Ok(cell) => valid_row.push(cell),
+          Err(err) => {
+            return Err(DatabaseError::Other(format!(
+              "Could not update {}: {} {}",
+              table_name,
+              err,
+              field.name()
+            )));
+          }
+        }
+      }
+      updates.push((index, valid_row));
+    }
+
+    for (index, valid_row) in updates {
+      let mut data_blockdisk = BlockDisk::new(self, table.data_block_offset())?;
+      unsafe { Row::update_row(index, valid_row, &mut data_blockdisk, table.schema())? };
+    }
+    Ok(())
+  }
+
+  /// Finds every row in the table that the where clause is true for, along
+  /// with the index of the row
+  fn matching_rows(
+    &mut self,
+    table: &OnDiskSchema,
+    fields: &[TableField],
+    where_clause: Option<&parser::Expr>,
+  ) -> Result<Vec<(u64, Row)>, DatabaseError> {
+    let predicate = match where_clause {
+      Some(where_clause) => Some(self.compile_expr(where_clause, fields, &[], &|_| None)?),
+      None => None,
+    };
+
+    let mut rows = vec![];
+    let mut index = 0;
+    while let Some((row_index, row)) = self.read_nth_row(table, index)? {
+      let is_match = match &predicate {
+        Some(predicate) => crate::expr::is_truthy(&predicate.eval(&row.as_cells(fields)?, self)?),
+        None => true,
+      };
+      if is_match {
+        rows.push((row_index, row));
+      }
+      index = row_index + 1;
+    }
+    Ok(rows)
+  }
+
+  /// Builds the table that a `FROM` clause reads from, joining the tables
+  /// together from left to right
+  fn join_clause(
+    &mut self,
+    join_clause: &parser::JoinClause,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use crate::table::{JoinKind, NestedLoopJoin};
+    use parser::JoinOperator;
+
+    let mut iter = self.table_or_subquery(&join_clause.table, scopes)?;
+    for join in join_clause.joins.iter() {
+      let right = self.table_or_subquery(&join.table, scopes)?;
+      let kind = match join.operator {
+        JoinOperator::Inner | JoinOperator::Cross => JoinKind::Inner,
+        JoinOperator::Left => JoinKind::Left,
+      };
+      let schema: Vec<TableField> = iter.schema().into_iter().chain(right.schema()).collect();
+      let constraint = match &join.constraint {
+        Some(constraint) => Some(self.compile_expr(constraint, &schema, scopes, &|_| None)?),
+        None => None,
+      };
+      iter = Box::new(NestedLoopJoin::new(iter, right, kind, constraint));
+    }
+    Ok(iter)
+  }
+
+  fn table_or_subquery(
+    &mut self,
+    table_or_subquery: &parser::TableOrSubquery,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use parser::TableOrSubquery;
+
+    match table_or_subquery {
+      TableOrSubquery::Table { name, alias } => {
+        let table = self.named_table(name.text(), scopes)?;
+        match alias {
+          Some(alias) => Ok(Box::new(table.qualify(alias.text()))),
+          None => Ok(table),
+        }
+      }
+      TableOrSubquery::Subquery { query, alias } => {
+        let table = self.select(query, scopes)?;
+        match alias {
+          Some(alias) => Ok(Box::new(table.qualify(alias.text()))),
+          None => Ok(table),
+        }
+      }
+    }
+  }
+
+  /// Finds the table that `name` refers to. Common table expressions
+  /// hide any tables on the disk with the same name.
+  fn named_table(
+    &mut self,
+    name: &str,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use crate::table::{SchemaReader, WorkingTable};
+
+    for (depth, scope) in scopes.iter().enumerate().rev() {
+      match scope {
+        Scope::Recursive {
+          name: cte_name,
+          fields,
+          row,
+          used,
+        } if cte_name == name => {
+          used.set(true);
+          mark_correlated(&scopes[depth..]);
+          return Ok(Box::new(WorkingTable::new(fields.clone(), row.clone())));
+        }
+        Scope::With { ctes, recursive } => {
+          if let Some(idx) = ctes.iter().position(|cte| cte.name.text() == name) {
+            // a CTE can only see the ones that were defined before it
+            let earlier = Scope::With {
+              ctes: ctes[..idx].to_vec(),
+              recursive: *recursive,
+            };
+            let mut cte_scopes = scopes[..depth].to_vec();
+            cte_scopes.push(&earlier);
+            return self.cte_table(&ctes[idx], *recursive, &cte_scopes);
+          }
+        }
+        _ => {}
+      }
+    }
+    Ok(Box::new(SchemaReader::new(self.get_table(name)?)))
+  }
+
+  /// Plans a common table expression. A CTE is planned again every time
+  /// it's used, just like a subquery in `FROM` would be.
+  fn cte_table(
+    &mut self,
+    cte: &parser::CommonTableExpression,
+    recursive: bool,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use crate::table::RecursiveCte;
+    use parser::CompoundOperator;
+
+    let query = &cte.query;
+    let recursive_part = match query.compound.last() {
+      Some(last) if recursive => match last.operator {
+        CompoundOperator::Union | CompoundOperator::UnionAll => Some(last),
+        _ => None,
+      },
+      _ => None,
+    };
+    if let Some(recursive_part) = recursive_part {
+      let with_scope;
+      let mut scopes = scopes.to_vec();
+      if let Some(with) = &query.with {
+        with_scope = Scope::With {
+          ctes: with.ctes.clone(),
+          recursive: with.recursive,
+        };
+        scopes.push(&with_scope);
+      }
+
+      // Everything before the last UNION is where the recursion starts
+      let initial_query = parser::SelectStatement {
+        with: None,
+        core: query.core.clone(),
+        compound: query.compound[..query.compound.len() - 1].to_vec(),
+        order_by: vec![],
+        limit: None,
+      };
+      let initial = self.select(&initial_query, &scopes)?;
+      let fields = cte_fields(cte, initial.schema())?;
+
+      let row = Rc::new(RefCell::new(None));
+      let used = Cell::new(false);
+      let scope = Scope::Recursive {
+        name: cte.name.text().to_string(),
+        fields: fields.clone(),
+        row: row.clone(),
+        used,
+      };
+      let mut recursive_scopes = scopes.clone();
+      recursive_scopes.push(&scope);
+      let step = self.select_core(&recursive_part.core, &[], &recursive_scopes)?;
+      let is_recursive = match &scope {
+        Scope::Recursive { used, .. } => used.get(),
+        _ => unreachable!(),
+      };
+
+      // a CTE that never refers to itself is just a normal select
+      if is_recursive {
+        if step.schema().len() != fields.len() {
+          return Err(DatabaseError::Other(format!(
+            "Error: SELECTs to the left and right of UNION do not have the same number of result columns in {}",
+            cte.name
+          )));
+        }
+        if !query.order_by.is_empty() {
+          return Err(DatabaseError::Other(format!(
+            "Error: ORDER BY is not supported in the recursive table {}",
+            cte.name
+          )));
+        }
+        let columns: Vec<Expression> = (0..fields.len()).map(Expression::Column).collect();
+        let iter = Box::new(RecursiveCte::new(
+          initial.map_schema(fields.clone(), columns.clone()),
+          step.map_schema(fields, columns),
+          row,
+          recursive_part.operator == CompoundOperator::UnionAll,
+        ));
+        return self.apply_limit(iter, query.limit.as_ref());
+      }
+    }
+
+    let table = self.select(query, scopes)?;
+    let fields = cte_fields(cte, table.schema())?;
+    let columns = (0..fields.len()).map(Expression::Column).collect();
+    Ok(Box::new(table.map_schema(fields, columns)))
+  }
+
+  /// Plans a select. `scopes` holds everything around it that it can
+  /// refer to, from the outermost in.
+  fn select(
+    &mut self,
+    select_statement: &parser::SelectStatement,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    let with_scope;
+    let mut scopes = scopes.to_vec();
+    if let Some(with) = &select_statement.with {
+      with_scope = Scope::With {
+        ctes: with.ctes.clone(),
+        recursive: with.recursive,
+      };
+      scopes.push(&with_scope);
+    }
+
+    let iter = if select_statement.compound.is_empty() {
+      self.select_core(&select_statement.core, &select_statement.order_by, &scopes)?
+    } else {
+      self.compound_select(select_statement, &scopes)?
+    };
+    self.apply_limit(iter, select_statement.limit.as_ref())
+  }
+
+  fn apply_limit(
+    &mut self,
+    iter: Box<dyn Table>,
+    limit: Option<&parser::Limit>,
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    match limit {
+      Some(limit) => {
+        // negative numbers mean there's no limit, like in sqlite
+        let limit_value = self.constant_integer(&limit.limit, "LIMIT")?;
+        let offset = match &limit.offset {
+          Some(offset) => self.constant_integer(offset, "OFFSET")?,
+          None => 0,
+        };
+        Ok(Box::new(iter.limit(
+          if limit_value < 0 {
+            None
+          } else {
+            Some(limit_value as u64)
+          },
+          std::cmp::max(offset, 0) as u64,
+        )))
+      }
+      None => Ok(iter),
+    }
+  }
+
+  /// Combines the results of each select in a compound select, from left to
+  /// right. Both sides have to have the same number of columns, and the
+  /// columns have to hold the same types of values.
+  fn compound_select(
+    &mut self,
+    select_statement: &parser::SelectStatement,
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use crate::table::Compound;
+    use parser::{CompoundOperator, Expr, LiteralValue};
+
+    let mut iter = self.select_core(&select_statement.core, &[], scopes)?;
+    for compound in select_statement.compound.iter() {
+      let right = self.select_core(&compound.core, &[], scopes)?;
+      let operator = match compound.operator {
+        CompoundOperator::Union => "UNION",
+        CompoundOperator::UnionAll => "UNION ALL",
+        CompoundOperator::Intersect => "INTERSECT",
+        CompoundOperator::Except => "EXCEPT",
+      };
+      let left_schema = iter.schema();
+      let right_schema = right.schema();
+      if left_schema.len() != right_schema.len() {
+        return Err(DatabaseError::Other(format!(
+          "Error: SELECTs to the left and right of {} do not have the same number of result columns",
+          operator
+        )));
+      }
+
+      // both sides get converted to the same schema, so that the rows
+      // from each side are laid out the same way
+      let mut schema = vec![];
+      for (idx, (left, right)) in left_schema.iter().zip(right_schema.iter()).enumerate() {
+        let kind = common_kind(left.kind(), right.kind()).ok_or_else(|| {
+          DatabaseError::Other(format!(
+            "Error: Column {} has a different type on each side of {}",
+            idx + 1,
+            operator
+          ))
+        })?;
+        schema.push(TableField::new(left.name().map(str::to_string), kind));
+      }
+      let columns: Vec<Expression> = (0..schema.len()).map(Expression::Column).collect();
+      iter = Box::new(Compound::new(
+        iter.map_schema(schema.clone(), columns.clone()),
+        right.map_schema(schema, columns),
+        compound.operator,
+      ));
+    }
+
+    // The ORDER BY of a compound select can only see the result columns
+    let schema = iter.schema();
+    let mut sort_keys = vec![];
+    for term in select_statement.order_by.iter() {
+      let key = match &term.expr {
+        Expr::LiteralValue(LiteralValue::NumericLiteral(n)) => {
+          Expression::Column(result_column_index(*n, schema.len())?)
+        }
+        expr => self.compile_expr(expr, &schema, scopes, &|_| None)?,
+      };
+      sort_keys.push((key, term.order));
+    }
+    if sort_keys.is_empty() {
+      Ok(iter)
+    } else {
+      Ok(Box::new(iter.sort(sort_keys)))
+    }
+  }
+
+  /// Builds a single select, sorting the rows by `order_by` before the
+  /// result columns are picked out
+  fn select_core(
+    &mut self,
+    core: &parser::SelectCore,
+    order_by: &[parser::OrderingTerm],
+    scopes: &[&Scope],
+  ) -> Result<Box<dyn Table>, DatabaseError> {
+    use crate::table::SingleRow;
+    use parser::{ColumnIdent, Expr, LiteralValue, ResultColumn};
+
+    let iter: Box<dyn Table> = match &core.from {
+      Some(from) => self.join_clause(from, scopes)?,
+      None => Box::new(SingleRow::default()),
+    };
+
+    let iter: Box<dyn Table> = match &core.where_clause {
+      Some(where_clause) => {
+        let predicate = self.compile_expr(where_clause, &iter.schema(), scopes, &|_| None)?;
+        Box::new(iter.filter(predicate))
+      }
+      None => iter,
+    };
+
+    // Everything after this point reads from the output of the aggregate
+    // (if there is one), so aggregate calls and GROUP BY expressions have
+    // to be swapped out for the columns that hold their values.
+    let mut aggregates = vec![];
+    for column in core.columns.iter() {
+      if let ResultColumn::Expr { value, .. } = column {
+        crate::expr::collect_aggregates(value, &mut aggregates);
+      }
+    }
+    if let Some(having) = &core.having {
+      crate::expr::collect_aggregates(having, &mut aggregates);
+    }
+    for term in order_by.iter() {
+      crate::expr::collect_aggregates(&term.expr, &mut aggregates);
+    }
+    let is_aggregate = !aggregates.is_empty() || !core.group_by.is_empty() || core.having.is_some();
+    let group_by = &core.group_by;
+    let substitute = |ast: &Expr| -> Option<Expression> {
+      if !is_aggregate {
+        return None;
+      }
+      if let Some(idx) = group_by.iter().position(|expr| expr == ast) {
+        return Some(Expression::Column(idx));
+      }
+      match ast {
+        Expr::FunctionCall(call) => aggregates
+          .iter()
+          .position(|aggregate| *aggregate == call)
+          .map(|idx| Expression::Column(group_by.len() + idx)),
+        _ => None,
+      }
+    };
+
+    let iter: Box<dyn Table> = if is_aggregate {
+      let prev_schema = iter.schema();
+      let mut schema = vec![];
+      let mut group_by_columns = vec![];
+      for expr in group_by.iter() {
+        let expression = self.compile_expr(expr, &prev_schema, scopes, &|_| None)?;
+        // grouping by a column keeps its name, so that it can still be
+        // referred to after the aggregate
+        schema.push(match &expression {
+          Expression::Column(idx) => prev_schema[*idx].clone(),
+          expression => TableField::new(None, expression.kind(&prev_schema)),
+        });
+        group_by_columns.push(expression);
+      }
+      let mut aggregate_calls = vec![];
+      for call in aggregates.iter() {
+        let aggregate_call = crate::expr::AggregateCall::compile(call, &prev_schema)?
+          .expect("only aggregate functions are collected");
+        schema.push(TableField::new(None, aggregate_call.kind(&prev_schema)));
+        aggregate_calls.push(aggregate_call);
+      }
+      Box::new(iter.aggregate(schema, group_by_columns, aggregate_calls))
+    } else {
+      iter
+    };
+
+    let iter: Box<dyn Table> = match &core.having {
+      Some(having) => {
+        let predicate = self.compile_expr(having, &iter.schema(), scopes, &substitute)?;
+        Box::new(iter.filter(predicate))
+      }
+      None => iter,
+    };
+
+    let prev_schema = iter.schema();
+    let mut next_schema = vec![];
+    let mut columns = vec![];
+    let mut aliases = vec![];
+    for column in core.columns.iter() {
+      match column {
+        ResultColumn::Asterisk | ResultColumn::TableAsterisk(_) if is_aggregate => {
+          return Err(DatabaseError::Other(
+            "Error: * can not be used with GROUP BY or aggregate functions".to_string(),
+          ));
+        }
+        ResultColumn::Asterisk => {
+          for (idx, field) in prev_schema.iter().enumerate() {
+            next_schema.push(field.clone());
+            columns.push(Expression::Column(idx));
+          }
+        }
+        ResultColumn::TableAsterisk(table) => {
+          let start = columns.len();
+          for (idx, field) in prev_schema.iter().enumerate() {
+            if field.table() == Some(table.text()) {
+              next_schema.push(field.clone());
+              columns.push(Expression::Column(idx));
             }
+          }
+          if columns.len() == start {
+            return Err(DatabaseError::Other(format!(
+              "Error: Could not find table {}",
+              table
+            )));
+          }
+        }
+        ResultColumn::Expr { value, alias } => {
+          let expression = self.compile_expr(value, &prev_schema, scopes, &substitute)?;
+          // Columns keep their name, anything more complicated
+          // than that needs an alias to get one.
+          let name = match (alias, value) {
+            (Some(alias), _) => Some(alias.text().to_string()),
+            (None, Expr::ColumnIdent(column_ident)) => Some(column_ident.column.text().to_string()),
+            (None, _) => None,
+          };
+          if let Some(alias) = alias {
+            aliases.push((alias.text(), columns.len()));
+          }
+          next_schema.push(TableField::new(name, expression.kind(&prev_schema)));
+          columns.push(expression);
+        }
+      }
+    }
+
+    // Sorting happens before the columns are picked out, so that rows can
+    // be sorted by columns that aren't in the result. Terms that refer to a
+    // result column (by alias, or by its position) use that column's expression.
+    let mut sort_keys = vec![];
+    for term in order_by.iter() {
+      let by_alias = match &term.expr {
+        Expr::ColumnIdent(ColumnIdent {
+          column,
+          table: None,
+        }) => aliases
+          .iter()
+          .find(|(alias, _)| *alias == column.text())
+          .map(|(_, idx)| *idx),
+        _ => None,
+      };
+      let key = match (&term.expr, by_alias) {
+        (_, Some(idx)) => columns[idx].clone(),
+        (Expr::LiteralValue(LiteralValue::NumericLiteral(n)), None) => {
+          columns[result_column_index(*n, columns.len())?].clone()
+        }
+        (expr, None) => self.compile_expr(expr, &prev_schema, scopes, &substitute)?,
+      };
+      sort_keys.push((key, term.order));
+    }
+    let iter: Box<dyn Table> = if sort_keys.is_empty() {
+      iter
+    } else {
+      Box::new(iter.sort(sort_keys))
+    };
+
+    let iter: Box<dyn Table> = Box::new(iter.map_schema(next_schema, columns));
+    if core.distinct {
+      Ok(Box::new(iter.distinct()))
+    } else {
+      Ok(iter)
+    }
+  }
 
-            let iter = crate::table::SchemaReader::new(table);
+  /// Compiles an expression that's evaluated against rows with `fields`,
+  /// planning any subqueries inside of it first
+  fn compile_expr(
+    &mut self,
+    ast: &parser::Expr,
+    fields: &[TableField],
+    scopes: &[&Scope],
+    substitute: &dyn Fn(&parser::Expr) -> Option<Expression>,
+  ) -> Result<Expression, DatabaseError> {
+    let mut resolved = vec![];
+    self.resolve_subqueries(ast, fields, scopes, substitute, &mut resolved)?;
+    Expression::compile_with(ast, fields, &|expr| {
+      substitute(expr).or_else(|| {
+        resolved
+          .iter()
+          .find(|(ast, _)| std::ptr::eq(*ast, expr))
+          .map(|(_, expression)| expression.clone())
+      })
+    })
+  }
 
-            let iter = iter.map_schema(next_schema, alias_mapping);
+  /// Finds the parts of `ast` that `Expression::compile` can't handle on
+  /// its own: subqueries, and columns that come from an outer query
+  fn resolve_subqueries<'a>(
+    &mut self,
+    ast: &'a parser::Expr,
+    fields: &[TableField],
+    scopes: &[&Scope],
+    substitute: &dyn Fn(&parser::Expr) -> Option<Expression>,
+    resolved: &mut Vec<(&'a parser::Expr, Expression)>,
+  ) -> Result<(), DatabaseError> {
+    use parser::{Expr, FunctionArgs, InList};
 
-            Ok(Some(Box::new(iter)))
+    if substitute(ast).is_some() {
+      return Ok(());
+    }
+    match ast {
+      Expr::LiteralValue(_) => {}
+      Expr::ColumnIdent(column_ident) => {
+        if crate::expr::resolve_column(column_ident, fields)?.is_none() {
+          if let Some(expression) = outer_column(column_ident, scopes)? {
+            resolved.push((ast, expression));
+          }
+        }
+      }
+      Expr::Unary { expr, .. } => {
+        self.resolve_subqueries(expr, fields, scopes, substitute, resolved)?;
+      }
+      Expr::Binary { lhs, rhs, .. } => {
+        self.resolve_subqueries(lhs, fields, scopes, substitute, resolved)?;
+        self.resolve_subqueries(rhs, fields, scopes, substitute, resolved)?;
+      }
+      Expr::FunctionCall(call) => {
+        if let FunctionArgs::Exprs(args) = &call.args {
+          for arg in args.iter() {
+            self.resolve_subqueries(arg, fields, scopes, substitute, resolved)?;
           }
-          None => Ok(None),
         }
       }
+      Expr::Parameter(parameter) => {
+        // parameters are numbered from 1, and are 0 if they haven't been numbered
+        let value = parameter
+          .index
+          .checked_sub(1)
+          .and_then(|idx| self.parameters.get(idx))
+          .cloned()
+          .ok_or_else(|| {
+            DatabaseError::Other(format!(
+              "Error: No value was bound to parameter {}",
+              parameter.text
+            ))
+          })?;
+        resolved.push((ast, Expression::Literal(value)));
+      }
+      Expr::Case {
+        operand,
+        when_then,
+        else_expr,
+      } => {
+        let exprs = operand
+          .iter()
+          .map(|operand| &**operand)
+          .chain(when_then.iter().flat_map(|(when, then)| vec![when, then]))
+          .chain(else_expr.iter().map(|else_expr| &**else_expr));
+        for expr in exprs {
+          self.resolve_subqueries(expr, fields, scopes, substitute, resolved)?;
+        }
+      }
+      Expr::In {
+        expr,
+        list: InList::Exprs(list),
+        ..
+      } => {
+        self.resolve_subqueries(expr, fields, scopes, substitute, resolved)?;
+        for item in list.iter() {
+          self.resolve_subqueries(item, fields, scopes, substitute, resolved)?;
+        }
+      }
+      Expr::In {
+        expr,
+        not,
+        list: InList::Subquery(query),
+      } => {
+        let expr = self.compile_expr(expr, fields, scopes, substitute)?;
+        let subquery = self.single_column_subquery(query, fields, scopes)?;
+        resolved.push((
+          ast,
+          Expression::InSubquery {
+            expr: Box::new(expr),
+            not: *not,
+            subquery,
+          },
+        ));
+      }
+      Expr::Exists(query) => {
+        let subquery = self.subquery(query, fields, scopes)?;
+        resolved.push((ast, Expression::Exists(subquery)));
+      }
+      Expr::Subquery(query) => {
+        let subquery = self.single_column_subquery(query, fields, scopes)?;
+        resolved.push((ast, Expression::Subquery(subquery)));
+      }
+    }
+    Ok(())
+  }
+
+  /// Plans a subquery inside of an expression that's evaluated against
+  /// rows with `fields`
+  fn subquery(
+    &mut self,
+    query: &parser::SelectStatement,
+    fields: &[TableField],
+    scopes: &[&Scope],
+  ) -> Result<Subquery, DatabaseError> {
+    let row = OuterRow::default();
+    let scope = Scope::Query {
+      fields: fields.to_vec(),
+      row: row.clone(),
+      correlated: Cell::new(false),
+    };
+    let mut inner_scopes = scopes.to_vec();
+    inner_scopes.push(&scope);
+    let table = self.select(query, &inner_scopes)?;
+    let is_correlated = match &scope {
+      Scope::Query { correlated, .. } => correlated.get(),
+      _ => unreachable!(),
+    };
+    Ok(Subquery::new(
+      table,
+      if is_correlated { Some(row) } else { None },
+    ))
+  }
+
+  /// Plans a subquery whose result is used as a single value
+  fn single_column_subquery(
+    &mut self,
+    query: &parser::SelectStatement,
+    fields: &[TableField],
+    scopes: &[&Scope],
+  ) -> Result<Subquery, DatabaseError> {
+    let subquery = self.subquery(query, fields, scopes)?;
+    let columns = subquery.schema().len();
+    if columns != 1 {
+      return Err(DatabaseError::Other(format!(
+        "Error: Subquery returns {} columns - expected 1",
+        columns
+      )));
+    }
+    Ok(subquery)
+  }
+
+  /// Evaluates an expression that can't refer to any columns, like the
+  /// value of a `LIMIT`, which has to be an integer
+  fn constant_integer(&mut self, ast: &parser::Expr, clause: &str) -> Result<i64, DatabaseError> {
+    match self
+      .compile_expr(ast, &[], &[], &|_| None)?
+      .eval(&[], self)?
+    {
+      schema::OwnedRowCell::Number { value, .. } => Ok(value),
+      _ => Err(DatabaseError::Other(format!(
+        "Error: {} must be an integer",
+        clause
+      ))),
     }
   }
 
-  fn insert_ast_row<'a>(
+  fn insert_ast_row(
     &mut self,
     schema: &schema::Schema,
-    ast: &[parser::Expr<'a>],
+    ast: &[parser::Expr],
     mapping: &BTreeMap<usize, usize>,
   ) -> Result<(), DatabaseError> {
     // We don't have defaults for columns (yet). Assert that the columns are the same length
     // at least.
+    if ast.len() != schema.fields().len() {
+      return Err(DatabaseError::Other(format!(
+        "Could not insert into {}: Number of values does not match number of columns",
+        schema.name()
+      )));
+    }
     let mut row = vec![];
     for i in 0..schema.fields().len() {
-      match schema::OwnedRowCell::from_ast_expr(&ast[mapping[&i]]) {
-        Some(cell) => {
-          row.push(cell);
-        }
-        None => {
-          return Err(DatabaseError::Other(format!(
-            "Could not insert into {}: Invalid Cell",
-            schema.name()
-          )));
-        }
-      }
+      // values can't refer to any columns, so they're evaluated against an empty row
+      let cell = self
+        .compile_expr(&ast[mapping[&i]], &[], &[], &|_| None)?
+        .eval(&[], self)?;
+      row.push(cell);
     }
 
     self.add_row(schema.name(), row)?;
@@ -354,12 +1149,20 @@ impl<T: Disk> Database<T> {
     }
     let mut valid_row = vec![];
     for (cell, field) in row.into_iter().zip(schema.schema().fields().iter()) {
+      if cell == schema::OwnedRowCell::Null {
+        return Err(DatabaseError::Other(format!(
+          "Could not insert into {}: Column {} can not hold NULL",
+          table,
+          field.name()
+        )));
+      }
       match cell.coerce_to(field) {
-        Some(field) => valid_row.push(field),
-        None => {
+        Ok(field) => valid_row.push(field),
+        Err(err) => {
           return Err(DatabaseError::Other(format!(
-            "Could not insert into {}: The data provided for column {} is invalid",
+            "Could not insert into {}: {} {}",
             table,
+            err,
             field.name()
           )));
         }
@@ -373,8 +1176,8 @@ impl<T: Disk> Database<T> {
   }
 
   #[allow(dead_code)]
-  fn read_table<'a>(
-    &'a mut self,
+  fn read_table(
+    &mut self,
     table_name: &str,
   ) -> Result<Vec<Vec<schema::OwnedRowCell>>, DatabaseError> {
     let table = self.get_table(table_name)?;
@@ -413,52 +1216,230 @@ impl<T: Disk> Database<T> {
     Ok(())
   }
 
-  pub fn schema(&mut self) -> Result<Vec<OnDiskSchema>, schema::SchemaError> {
-    let schema_block_offset = self.meta.schema_block_offset;
-    let mut reader = crate::BlockDisk::new(self, schema_block_offset)?;
-    OnDiskSchema::read_tables(&mut reader)
-  }
-
-  /// Initializes a new database on the provided disk
-  /// There should be no information on the provided disk
-  pub fn new(mut disk: T) -> io::Result<Self> {
-    // version 1, block size of 2048
-    let block_size_exp = 6 as u8;
-    let version = 1;
-    let block_size = 2u64.pow(block_size_exp as u32);
-    // create a new root block
-    let root_block = Block::new(0, block_size);
-    root_block.persist(&mut disk)?;
+  fn drop_table(
+    &mut self,
+    drop_table_statement: &parser::DropTableStatement,
+  ) -> Result<(), DatabaseError> {
+    let table_name = drop_table_statement.table_name.text();
+    let mut tables = self.schema()?;
+    let table = match tables
+      .iter()
+      .position(|table| table.schema().name() == table_name)
+    {
+      Some(idx) => tables.remove(idx),
+      None if drop_table_statement.if_exists => return Ok(()),
+      None => {
+        return Err(DatabaseError::TableNotFound {
+          table_name: table_name.to_string(),
+        });
+      }
+    };
 
-    let schema_block = Block::new(block_size, block_size);
-    schema_block.persist(&mut disk)?;
-    let meta = DatabaseMeta::new(version, block_size_exp);
-    meta.persist(&mut disk)?;
-    Ok(Database { disk, meta })
+    self.write_schema(&tables)?;
+    self.free_blocks(table.data_block_offset())?;
+    Ok(())
   }
 
-  pub fn from_disk(mut disk: T) -> io::Result<Self> {
-    let meta = DatabaseMeta::from_disk(&mut disk)?;
-
-    Ok(Database { disk, meta })
-  }
-}
+  fn alter_table(
+    &mut self,
+    alter_table_statement: &parser::AlterTableStatement,
+  ) -> Result<(), DatabaseError> {
+    use parser::AlterTableAction;
+    use schema::SchemaField;
 
-use crate::blockdisk::BlockAllocator;
+    let table_name = alter_table_statement.table_name.text();
+    let mut tables = self.schema()?;
+    let idx = tables
+      .iter()
+      .position(|table| table.schema().name() == table_name)
+      .ok_or_else(|| DatabaseError::TableNotFound {
+        table_name: table_name.to_string(),
+      })?;
+    let schema = tables[idx].schema().clone();
+    let data_block_offset = tables[idx].data_block_offset();
+    let column_exists = |name: &str| -> Result<(), DatabaseError> {
+      match schema.field(name) {
+        Some(_) => Err(DatabaseError::Other(format!(
+          "Could not alter {}: Column {} already exists",
+          table_name, name
+        ))),
+        None => Ok(()),
+      }
+    };
 
-impl<T: Disk> BlockAllocator for Database<T> {
-  fn allocate_block(&mut self) -> io::Result<Block> {
-    let next_block_offset = self.meta.num_allocated_blocks * self.meta.block_size();
-    log::debug!("Allocating block at offset {}", next_block_offset);
-    self.disk.seek(io::SeekFrom::Start(next_block_offset))?;
-    let block = Block::new(next_block_offset, self.meta.block_size());
-    self.meta.num_allocated_blocks += 1;
-    self.meta.persist(&mut self.disk)?;
-    block.persist(&mut self.disk)?;
-    Ok(block)
-  }
-  fn read_block(&mut self, offset: u64) -> io::Result<Block> {
-    log::debug!("Reading block at offset {}", offset);
+    match &alter_table_statement.action {
+      AlterTableAction::RenameTable(next_name) => {
+        if tables
+          .iter()
+          .any(|table| table.schema().name() == next_name.text())
+        {
+          return Err(DatabaseError::Other(format!(
+            "Could not rename {}: table with the same name already exists",
+            table_name
+          )));
+        }
+        let schema = Schema::from_fields(next_name.text().to_string(), schema.fields().to_vec());
+        tables[idx] = OnDiskSchema::new(data_block_offset, schema);
+        self.write_schema(&tables)?;
+      }
+      AlterTableAction::RenameColumn { from, to } => {
+        if schema.field(from.text()).is_none() {
+          return Err(DatabaseError::Other(format!(
+            "Could not alter {}: Column {} was not found in table",
+            table_name,
+            from.text()
+          )));
+        }
+        column_exists(to.text())?;
+        let fields = schema
+          .fields()
+          .iter()
+          .map(|field| {
+            if field.name() == from.text() {
+              SchemaField::new(field.kind().clone(), to.text().to_string())
+            } else {
+              Ok(field.clone())
+            }
+          })
+          .collect::<Result<Vec<_>, schema::FieldError>>()?;
+        let schema = Schema::from_fields(table_name.to_string(), fields);
+        tables[idx] = OnDiskSchema::new(data_block_offset, schema);
+        self.write_schema(&tables)?;
+      }
+      AlterTableAction::AddColumn(column_def) => {
+        let field = SchemaField::from_column_def(column_def)?;
+        column_exists(field.name())?;
+        // NULL can't be stored on the disk yet, so there's nothing the
+        // rows already in the table could have in the new column
+        if self.read_nth_row(&tables[idx], 0)?.is_some() {
+          return Err(DatabaseError::Other(format!(
+            "Could not alter {}: Columns can only be added to an empty table",
+            table_name
+          )));
+        }
+        let mut fields = schema.fields().to_vec();
+        fields.push(field);
+        let next_schema = Schema::from_fields(table_name.to_string(), fields);
+
+        // Every row gets wider, so the blocks have to be laid out again.
+        // There are no rows, but there may still be tombstones to drop.
+        tables[idx] = self.create_table_blocks(next_schema)?;
+        self.write_schema(&tables)?;
+        self.free_blocks(data_block_offset)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Lays out a new, empty set of blocks for `next_schema`
+  fn create_table_blocks(&mut self, next_schema: Schema) -> Result<OnDiskSchema, DatabaseError> {
+    let data_block = self.allocate_block()?;
+    let data_block_offset = data_block.meta().offset();
+    let mut data_blockdisk = BlockDisk::from_block(self, data_block)?;
+    unsafe { schema::Row::init_table(&next_schema, &mut data_blockdisk)? };
+    Ok(OnDiskSchema::new(data_block_offset, next_schema))
+  }
+
+  /// Replaces every entry in the schema block with `tables`
+  fn write_schema(&mut self, tables: &[OnDiskSchema]) -> Result<(), DatabaseError> {
+    let schema_block_offset = self.meta.schema_block_offset;
+    let mut blockdisk = BlockDisk::new(self, schema_block_offset)?;
+    OnDiskSchema::write_tables(tables, &mut blockdisk)?;
+    Ok(())
+  }
+
+  /// Puts the chain of blocks starting at `start_block_offset` onto the
+  /// front of the free list, so that they get handed out by `allocate_block`
+  fn free_blocks(&mut self, start_block_offset: u64) -> io::Result<()> {
+    let mut last_block = self.read_block(start_block_offset)?;
+    while let Some(next_block) = last_block.meta().next_block() {
+      last_block = self.read_block(next_block)?;
+    }
+    log::debug!(
+      "Freeing blocks {} through {}",
+      start_block_offset,
+      last_block.meta().offset()
+    );
+    let free_block_offset = self.meta.free_block_offset;
+    last_block.set_next_block(if free_block_offset == 0 {
+      None
+    } else {
+      Some(free_block_offset)
+    });
+    self.write_block(&last_block)?;
+
+    self.meta.free_block_offset = start_block_offset;
+    self.meta.persist(&mut self.disk)?;
+    Ok(())
+  }
+
+  pub fn schema(&mut self) -> Result<Vec<OnDiskSchema>, schema::SchemaError> {
+    let schema_block_offset = self.meta.schema_block_offset;
+    let mut reader = crate::BlockDisk::new(self, schema_block_offset)?;
+    OnDiskSchema::read_tables(&mut reader)
+  }
+
+  /// Initializes a new database on the provided disk
+  /// There should be no information on the provided disk
+  pub fn new(mut disk: T) -> io::Result<Self> {
+    // version 1, block size of 2048
+    let block_size_exp = 6_u8;
+    let version = 1;
+    let block_size = 2u64.pow(block_size_exp as u32);
+    // create a new root block
+    let root_block = Block::new(0, block_size);
+    root_block.persist(&mut disk)?;
+
+    let schema_block = Block::new(block_size, block_size);
+    schema_block.persist(&mut disk)?;
+    let meta = DatabaseMeta::new(version, block_size_exp);
+    meta.persist(&mut disk)?;
+    Ok(Database {
+      disk,
+      meta,
+      parameters: vec![],
+    })
+  }
+
+  pub fn from_disk(mut disk: T) -> io::Result<Self> {
+    let meta = DatabaseMeta::from_disk(&mut disk)?;
+
+    Ok(Database {
+      disk,
+      meta,
+      parameters: vec![],
+    })
+  }
+}
+
+use crate::blockdisk::BlockAllocator;
+
+impl<T: Disk> BlockAllocator for Database<T> {
+  fn allocate_block(&mut self) -> io::Result<Block> {
+    if self.meta.free_block_offset != 0 {
+      // reuse a block from the free list before growing the file
+      let free_block = self.read_block(self.meta.free_block_offset)?;
+      log::debug!(
+        "Reusing free block at offset {}",
+        free_block.meta().offset()
+      );
+      self.meta.free_block_offset = free_block.meta().next_block().unwrap_or(0);
+      self.meta.persist(&mut self.disk)?;
+      let block = Block::new(free_block.meta().offset(), self.meta.block_size());
+      block.persist(&mut self.disk)?;
+      return Ok(block);
+    }
+    let next_block_offset = self.meta.num_allocated_blocks * self.meta.block_size();
+    log::debug!("Allocating block at offset {}", next_block_offset);
+    self.disk.seek(io::SeekFrom::Start(next_block_offset))?;
+    let block = Block::new(next_block_offset, self.meta.block_size());
+    self.meta.num_allocated_blocks += 1;
+    self.meta.persist(&mut self.disk)?;
+    block.persist(&mut self.disk)?;
+    Ok(block)
+  }
+  fn read_block(&mut self, offset: u64) -> io::Result<Block> {
+    log::debug!("Reading block at offset {}", offset);
     Block::from_disk(offset, self.meta.block_size(), &mut self.disk)
   }
   fn write_block(&mut self, block: &Block) -> io::Result<()> {
@@ -468,8 +1449,109 @@ impl<T: Disk> BlockAllocator for Database<T> {
 }
 use crate::table::RowReader;
 
+/// Something around a query that gives names to things the query can use
+enum Scope {
+  /// A query that a subquery is nested in. Columns that the subquery can't
+  /// find in its own tables are looked for here.
+  Query {
+    fields: Vec<TableField>,
+    /// The row that the outer query is on, while the subquery runs
+    row: OuterRow,
+    /// Whether anything in the subquery refers to this query, or to one
+    /// that's around it
+    correlated: Cell<bool>,
+  },
+  /// The tables named by a WITH clause
+  With {
+    ctes: Vec<parser::CommonTableExpression>,
+    recursive: bool,
+  },
+  /// The recursive part of a recursive CTE, where the name of the CTE
+  /// refers to the row that's being recursed on
+  Recursive {
+    name: String,
+    fields: Vec<TableField>,
+    row: Rc<RefCell<Option<Row>>>,
+    used: Cell<bool>,
+  },
+}
+
+/// Makes every subquery in `scopes` run again whenever its outer row
+/// changes, because something inside of it depends on a row from outside
+fn mark_correlated(scopes: &[&Scope]) {
+  for scope in scopes.iter() {
+    if let Scope::Query { correlated, .. } = scope {
+      correlated.set(true);
+    }
+  }
+}
+
+/// Looks for a column in the queries around a subquery, from the
+/// innermost one out
+fn outer_column(
+  column_ident: &parser::ColumnIdent,
+  scopes: &[&Scope],
+) -> Result<Option<Expression>, DatabaseError> {
+  for (depth, scope) in scopes.iter().enumerate().rev() {
+    if let Scope::Query { fields, row, .. } = scope {
+      if let Some(idx) = crate::expr::resolve_column(column_ident, fields)? {
+        mark_correlated(&scopes[depth..]);
+        return Ok(Some(Expression::Outer {
+          row: row.clone(),
+          idx,
+          kind: fields[idx].kind().clone(),
+        }));
+      }
+    }
+  }
+  Ok(None)
+}
+
+/// The columns of a CTE, which get renamed if it has a list of columns
+fn cte_fields(
+  cte: &parser::CommonTableExpression,
+  schema: Vec<TableField>,
+) -> Result<Vec<TableField>, DatabaseError> {
+  if !cte.columns.is_empty() && cte.columns.len() != schema.len() {
+    return Err(DatabaseError::Other(format!(
+      "Error: Table {} has {} values for {} columns",
+      cte.name,
+      schema.len(),
+      cte.columns.len()
+    )));
+  }
+  Ok(
+    schema
+      .into_iter()
+      .enumerate()
+      .map(|(idx, field)| {
+        let name = match cte.columns.get(idx) {
+          Some(column) => Some(column.text().to_string()),
+          None => field.name().map(str::to_string),
+        };
+        TableField::new(name, field.kind().clone()).qualify(cte.name.text())
+      })
+      .collect(),
+  )
+}
+
+/// The index of the result column that `ORDER BY 2` refers to
+fn result_column_index(n: i64, num_columns: usize) -> Result<usize, DatabaseError> {
+  if n < 1 || n as usize > num_columns {
+    return Err(DatabaseError::Other(format!(
+      "Error: ORDER BY term {} is out of range, there are {} result columns",
+      n, num_columns
+    )));
+  }
+  Ok(n as usize - 1)
+}
+
 impl<T: Disk> RowReader for Database<T> {
-  fn read_nth_row(&mut self, schema: &OnDiskSchema, index: u64) -> Result<Option<Row>, TableError> {
+  fn read_nth_row(
+    &mut self,
+    schema: &OnDiskSchema,
+    index: u64,
+  ) -> Result<Option<(u64, Row)>, TableError> {
     // TODO :: cache this because it's gonna be SLOOWWWWWW
     log::debug!("Reading row {} for table {}", index, schema.schema().name());
     let mut blockdisk = BlockDisk::new(self, schema.data_block_offset())?;
@@ -478,13 +1560,21 @@ impl<T: Disk> RowReader for Database<T> {
       Row::sizeof_row_on_disk(schema.schema()) as u64 * index,
     ))?;
 
-    let row = Row::from_schema(&mut blockdisk, schema.schema())?;
-    if row.is_last_row() {
-      log::debug!("row is last row!");
-      Ok(None)
-    } else {
-      log::debug!("more rows to go!");
-      Ok(Some(row))
+    let mut index = index;
+    loop {
+      // rows are stored back to back, so we can keep reading until we
+      // find one that's still alive
+      let row = Row::from_schema(&mut blockdisk, schema.schema())?;
+      if row.is_last_row() {
+        log::debug!("row is last row!");
+        return Ok(None);
+      } else if row.is_deleted() {
+        log::debug!("row {} is deleted, skipping", index);
+        index += 1;
+      } else {
+        log::debug!("more rows to go!");
+        return Ok(Some((index, row)));
+      }
     }
   }
 }
@@ -503,10 +1593,8 @@ mod tests {
     let schema = Schema::from_fields(
       "users".into(),
       vec![
-        SchemaField::new(FieldKind::Number(8), "id".into())
-          .map_err(|err| SchemaError::from(err))?,
-        SchemaField::new(FieldKind::Str(20), "username".into())
-          .map_err(|err| SchemaError::from(err))?,
+        SchemaField::new(FieldKind::Number(8), "id".into()).map_err(SchemaError::from)?,
+        SchemaField::new(FieldKind::Str(20), "username".into()).map_err(SchemaError::from)?,
       ],
     );
     use schema::OwnedRowCell;
@@ -568,4 +1656,1126 @@ mod tests {
     Ok(())
   }
 
+  fn run_query(
+    database: &mut Database<io::Cursor<Vec<u8>>>,
+    query: &str,
+  ) -> Vec<Vec<schema::OwnedRowCell>> {
+    let mut rows = vec![];
+    database
+      .execute_query(query, |row| {
+        if let Some(row) = row {
+          rows.push(row);
+        }
+      })
+      .unwrap();
+    rows
+  }
+
+  fn number(value: i64) -> schema::OwnedRowCell {
+    schema::OwnedRowCell::Number { value, size: 8 }
+  }
+
+  #[test]
+  fn test_select_where() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, active integer);
+      insert into users (id, active) values (1, 1), (2, 0), (3, 1);",
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users where active;"),
+      vec![vec![number(1)], vec![number(3)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users where 0;"),
+      Vec::<Vec<schema::OwnedRowCell>>::new()
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_select_expressions() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table items (id integer, price integer, qty integer(2));
+      insert into items (id, price, qty) values (1, 10, 2), (2, 3, -5), (3, 7, 1 + 2);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id, price * qty as total from items where qty > 0 and not id = 3;"
+      ),
+      vec![vec![number(1), number(20)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select -qty from items where price <> 10;"),
+      vec![vec![number(5)], vec![number(-3)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select 1 + 2 * 3, NULL;"),
+      vec![vec![number(7), schema::OwnedRowCell::Null]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select -9223372036854775808, 0x10 - -1, 0xffffffffffffffff;"
+      ),
+      vec![vec![number(i64::MIN), number(17), number(-1)]]
+    );
+    assert!(matches!(
+      database.execute_query("select 1.5;", |_| ()),
+      Err(DatabaseQueryError::AstError(_))
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn test_delete() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer);
+      insert into users (id) values (1), (2), (3), (4);
+      delete from users where id = 1 or id = 3;",
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users;"),
+      vec![vec![number(2)], vec![number(4)]]
+    );
+    run_query(
+      &mut database,
+      "delete from users; insert into users (id) values (5);",
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users;"),
+      vec![vec![number(5)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_update() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, score integer, name varchar(5));
+      insert into users (id, score, name) values (1, 10, 'a'), (2, 20, 'b'), (3, 30, 'c');
+      delete from users where id = 2;
+      update users set score = score + id, name = 'cc' where id >= 2;",
+    );
+    let string = |value: &str| schema::OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 5,
+    };
+    assert_eq!(
+      run_query(&mut database, "select id, score, name from users;"),
+      vec![
+        vec![number(1), number(10), string("a")],
+        vec![number(3), number(33), string("cc")],
+      ]
+    );
+    for query in &[
+      "update users set missing = 1;",
+      "update users set score = NULL;",
+      "update users set name = 1;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    // strings that don't fit are an error, rather than being cut off
+    match database.execute_query("update users set name = 'truncated';", |_| ()) {
+      Err(DatabaseQueryError::InternalError(DatabaseError::Other(message))) => {
+        assert_eq!(
+          message,
+          "Could not update users: value too long for column name"
+        )
+      }
+      result => panic!("{:?}", result),
+    }
+    assert_eq!(
+      run_query(&mut database, "select name from users;"),
+      vec![vec![string("a")], vec![string("cc")]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_update_is_all_or_nothing() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table small (a integer(1));
+      insert into small (a) values (1), (2);",
+    );
+    // 100 fits in the column but 200 doesn't, so neither row changes
+    assert!(database
+      .execute_query("update small set a = a * 100;", |_| ())
+      .is_err());
+    assert_eq!(
+      run_query(&mut database, "select a from small;"),
+      vec![
+        vec![schema::OwnedRowCell::Number { value: 1, size: 1 }],
+        vec![schema::OwnedRowCell::Number { value: 2, size: 1 }],
+      ]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_drop_table() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(50));
+      insert into users (id, name) values (1, 'a'), (2, 'b'), (3, 'c'), (4, 'd');
+      create table items (id integer);
+      insert into items (id) value (1);
+      drop table users;",
+    );
+    assert!(database.get_table("users").is_err());
+    assert!(database.execute_query("drop table users;", |_| ()).is_err());
+    run_query(&mut database, "drop table if exists users;");
+    assert_ne!(database.meta.free_block_offset, 0);
+
+    // the new table should fit entirely in the blocks that were freed
+    let num_allocated_blocks = database.meta.num_allocated_blocks;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(50));
+      insert into users (id, name) values (5, 'e'), (6, 'f');",
+    );
+    assert_eq!(database.meta.num_allocated_blocks, num_allocated_blocks);
+    assert_eq!(
+      run_query(&mut database, "select id from users;"),
+      vec![vec![number(5)], vec![number(6)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from items;"),
+      vec![vec![number(1)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_alter_table() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(10));
+      insert into users (id, name) values (1, 'a');
+      delete from users;
+      alter table users add column age integer(2);
+      insert into users (id, name, age) values (1, 'a', 10), (2, 'b', 20), (3, 'c', 30);
+      delete from users where id = 2;
+      alter table users rename column name to username;
+      alter table users rename to people;
+      insert into people (id, username, age) value (4, 'd', 40);",
+    );
+    let string = |value: &str| schema::OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 10,
+    };
+    let small_number = |value| schema::OwnedRowCell::Number { value, size: 2 };
+    assert_eq!(
+      run_query(&mut database, "select id, username, age from people;"),
+      vec![
+        vec![number(1), string("a"), small_number(10)],
+        vec![number(3), string("c"), small_number(30)],
+        vec![number(4), string("d"), small_number(40)],
+      ]
+    );
+    assert!(database.get_table("users").is_err());
+    for query in &[
+      // the rows already there would have nothing to put in the column
+      "alter table people add column score integer;",
+      "alter table people add column id integer;",
+      "alter table people rename column missing to other;",
+      "alter table people rename column id to age;",
+      "alter table missing rename to other;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_type_names() -> Result<(), DatabaseError> {
+    use schema::{FieldError, FieldKind};
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table everything (
+        a integer, b integer(2), c int, d int(11), e smallint, f bigint,
+        g blob, h blob(16), i varchar, j text, k text(500), l char, m char(3)
+      );
+      insert into everything (a, b, c, d, e, f, g, h, i, j, k, l, m)
+        value (1, 2, 3, 4, 5, 6, x'00', x'01', 'i', 'j', 'k', 'l', 'mmm');",
+    );
+    let table = database.get_table("everything")?;
+    let kinds = table
+      .schema()
+      .fields()
+      .iter()
+      .map(|field| field.kind().clone())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      kinds,
+      vec![
+        FieldKind::Number(8),
+        FieldKind::Number(2),
+        FieldKind::Number(4),
+        FieldKind::Number(4),
+        FieldKind::Number(2),
+        FieldKind::Number(8),
+        FieldKind::Blob(100),
+        FieldKind::Blob(16),
+        FieldKind::Str(128),
+        FieldKind::Str(128),
+        FieldKind::Str(500),
+        FieldKind::Str(1),
+        FieldKind::Str(3),
+      ]
+    );
+    assert_eq!(
+      run_query(&mut database, "select e, m from everything;"),
+      vec![vec![
+        schema::OwnedRowCell::Number { value: 5, size: 2 },
+        schema::OwnedRowCell::Str {
+          value: "mmm".to_string(),
+          max_size: 3
+        },
+      ]]
+    );
+    assert!(database
+      .execute_query("create table bad (a number);", |_| ())
+      .is_err());
+    // sizes have to fit in the field, rather than wrapping around
+    let field_error =
+      |database: &mut Database<_>, query: &str| match database.execute_query(query, |_| ()) {
+        Err(DatabaseQueryError::InternalError(DatabaseError::FieldError(err))) => err,
+        result => panic!("{} gave {:?}", query, result),
+      };
+    assert_eq!(
+      field_error(&mut database, "create table bad (a integer(257));"),
+      FieldError::InvalidSize(257)
+    );
+    assert_eq!(
+      field_error(&mut database, "create table bad (a integer(256));"),
+      FieldError::InvalidSize(256)
+    );
+    assert_eq!(
+      field_error(&mut database, "create table bad (a integer(3));"),
+      FieldError::InvalidNumberType(3)
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_type_names_are_not_reserved() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table notes (text varchar(10), int integer, blob char);
+      insert into notes (text, int, blob) values ('hi', 1, 'b');",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select text, int from notes where blob = 'b';"
+      ),
+      vec![vec![
+        schema::OwnedRowCell::Str {
+          value: "hi".to_string(),
+          max_size: 10
+        },
+        number(1),
+      ]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select typeof(1) as text;"),
+      vec![vec![schema::OwnedRowCell::Str {
+        value: "integer".to_string(),
+        max_size: 7
+      }]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_joins() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, team integer);
+      create table teams (id integer, size integer);
+      insert into users (id, team) values (1, 10), (2, 20), (3, 10);
+      insert into teams (id, size) values (10, 5), (30, 7);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select users.id, t.size from users join teams as t on users.team = t.id;"
+      ),
+      vec![vec![number(1), number(5)], vec![number(3), number(5)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select users.id, teams.id from users left join teams on team = teams.id where users.id < 3;"
+      ),
+      vec![
+        vec![number(1), number(10)],
+        vec![number(2), schema::OwnedRowCell::Null]
+      ]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select users.id, teams.* from users, teams where users.id = 1;"
+      ),
+      vec![
+        vec![number(1), number(10), number(5)],
+        vec![number(1), number(30), number(7)]
+      ]
+    );
+    // both tables have an id column
+    assert!(database
+      .execute_query("select id from users, teams;", |_| ())
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_order_by() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, team integer);
+      insert into users (id, team) values (1, 20), (2, 10), (3, 20), (4, 10);",
+    );
+    let ids = |database: &mut Database<_>, query| -> Vec<Vec<schema::OwnedRowCell>> {
+      run_query(database, query)
+        .into_iter()
+        .map(|row| vec![row[0].clone()])
+        .collect()
+    };
+    let expected = vec![
+      vec![number(4)],
+      vec![number(2)],
+      vec![number(3)],
+      vec![number(1)],
+    ];
+    assert_eq!(
+      ids(
+        &mut database,
+        "select id from users order by team, id desc;"
+      ),
+      expected
+    );
+    assert_eq!(
+      ids(
+        &mut database,
+        "select id, team as t from users order by t asc, 1 desc;"
+      ),
+      expected
+    );
+    assert_eq!(
+      ids(&mut database, "select id from users order by -id;"),
+      vec![
+        vec![number(4)],
+        vec![number(3)],
+        vec![number(2)],
+        vec![number(1)],
+      ]
+    );
+    assert!(database
+      .execute_query("select id from users order by 2;", |_| ())
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_limit() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer);
+      insert into users (id) values (1), (2), (3), (4), (5);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id from users order by id desc limit 2;"
+      ),
+      vec![vec![number(5)], vec![number(4)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users limit 2 offset 1 + 1;"),
+      vec![vec![number(3)], vec![number(4)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users limit -1 offset 4;"),
+      vec![vec![number(5)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from users limit 10 offset 10;"),
+      Vec::<Vec<schema::OwnedRowCell>>::new()
+    );
+    assert!(database
+      .execute_query("select id from users limit 'a';", |_| ())
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_group_by() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, team integer, score integer);
+      insert into users (id, team, score) values (1, 20, 5), (2, 10, 3), (3, 20, 8), (4, 30, 1);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select team, count(*), sum(score), min(score), max(score), avg(score)
+        from users group by team order by team;"
+      ),
+      vec![
+        vec![
+          number(10),
+          number(1),
+          number(3),
+          number(3),
+          number(3),
+          number(3)
+        ],
+        vec![
+          number(20),
+          number(2),
+          number(13),
+          number(5),
+          number(8),
+          number(6)
+        ],
+        vec![
+          number(30),
+          number(1),
+          number(1),
+          number(1),
+          number(1),
+          number(1)
+        ],
+      ]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select team % 20, count(id) as n from users group by team % 20 having count(*) > 1;"
+      ),
+      vec![vec![number(0), number(2)], vec![number(10), number(2)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select count(*), max(score) - min(score) from users where id > 1;"
+      ),
+      vec![vec![number(3), number(7)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select count(*), sum(score) from users where 0;"
+      ),
+      vec![vec![number(0), schema::OwnedRowCell::Null]]
+    );
+    for query in &[
+      "select * from users group by team;",
+      "select id from users group by team;",
+      "select id from users where count(*) > 1;",
+      "select sum(*) from users;",
+      "select max(count(*)) from users;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_group_by_mixed_widths() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table t (a integer(4), b integer(8));
+      insert into t (a, b) values (1, 2), (1, 3);",
+    );
+    // the group keys and results are as wide as their columns, even when
+    // the value they were worked out from is narrower
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select count(*) from t group by iif(1, a, b);"
+      ),
+      vec![vec![number(2)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select max(iif(1, a, b)) from t;"),
+      vec![vec![number(1)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_distinct() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, team integer);
+      insert into users (id, team) values (1, 20), (2, 10), (3, 20), (4, 10), (5, 30);",
+    );
+    assert_eq!(
+      run_query(&mut database, "select distinct team from users;"),
+      vec![vec![number(20)], vec![number(10)], vec![number(30)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select distinct team from users order by team desc limit 2;"
+      ),
+      vec![vec![number(30)], vec![number(20)]]
+    );
+    assert_eq!(
+      run_query(&mut database, "select all team from users where id < 3;"),
+      vec![vec![number(20)], vec![number(10)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_compound_select() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(10));
+      create table admins (id integer, name varchar(20));
+      insert into users (id, name) values (1, 'a'), (2, 'b'), (3, 'c');
+      insert into admins (id, name) values (3, 'c'), (4, 'd'), (4, 'd');",
+    );
+    let ids = |rows: Vec<Vec<schema::OwnedRowCell>>| -> Vec<schema::OwnedRowCell> {
+      rows.into_iter().map(|mut row| row.remove(0)).collect()
+    };
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id from users union all select id from admins;"
+      )),
+      vec![1, 2, 3, 3, 4, 4]
+        .into_iter()
+        .map(number)
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id, name from users union select id, name from admins;"
+      )),
+      vec![1, 2, 3, 4].into_iter().map(number).collect::<Vec<_>>()
+    );
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id from admins intersect select id from users;"
+      )),
+      vec![number(3)]
+    );
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id from admins except select id from users;"
+      )),
+      vec![number(4)]
+    );
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id from users union select id from admins order by 1 desc limit 2;"
+      )),
+      vec![number(4), number(3)]
+    );
+    assert_eq!(
+      ids(run_query(
+        &mut database,
+        "select id as n from users except select 2 order by n desc;"
+      )),
+      vec![number(3), number(1)]
+    );
+    for query in &[
+      "select id from users union select id, name from admins;",
+      "select id from users union select name from admins;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_subqueries() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, team integer);
+      create table posts (id integer, user_id integer);
+      insert into users (id, team) values (1, 10), (2, 10), (3, 20);
+      insert into posts (id, user_id) values (1, 1), (2, 1), (3, 3);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select t.team, t.n from (select team, count(*) as n from users group by team) as t
+        where t.n > 1;"
+      ),
+      vec![vec![number(10), number(2)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id from users where id in (select user_id from posts);"
+      ),
+      vec![vec![number(1)], vec![number(3)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id from users where id not in (select user_id from posts) and id in (1, 2);"
+      ),
+      vec![vec![number(2)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id from users where not exists (select * from posts where posts.user_id = users.id);"
+      ),
+      vec![vec![number(2)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id, (select count(*) from posts where user_id = users.id) from users;"
+      ),
+      vec![
+        vec![number(1), number(2)],
+        vec![number(2), number(0)],
+        vec![number(3), number(1)],
+      ]
+    );
+    assert_eq!(
+      run_query(&mut database, "select (select max(id) from users) + 1;"),
+      vec![vec![number(4)]]
+    );
+    run_query(
+      &mut database,
+      "delete from posts where user_id in (select id from users where team = 20);",
+    );
+    assert_eq!(
+      run_query(&mut database, "select id from posts;"),
+      vec![vec![number(1)], vec![number(2)]]
+    );
+    assert!(database
+      .execute_query(
+        "select id in (select id, team from users) from users;",
+        |_| ()
+      )
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_with() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table employees (id integer, manager_id integer);
+      insert into employees (id, manager_id) values (1, 0), (2, 1), (3, 1), (4, 2), (5, 4), (6, 0);",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "with managers(id) as (select manager_id from employees where manager_id > 0),
+          counts as (select id, count(*) as n from managers group by id)
+        select counts.id, counts.n from counts where counts.n > 1;"
+      ),
+      vec![vec![number(1), number(2)]]
+    );
+    // a CTE hides the table with the same name, and can be used more than once
+    assert_eq!(
+      run_query(
+        &mut database,
+        "with employees as (select id from employees where id < 3)
+        select a.id, b.id from employees as a join employees as b on a.id < b.id;"
+      ),
+      vec![vec![number(1), number(2)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "with recursive reports(id, depth) as (
+          select id, 0 from employees where id = 2
+          union all
+          select employees.id, reports.depth + 1 from employees join reports
+            on employees.manager_id = reports.id
+        )
+        select id, depth from reports;"
+      ),
+      vec![
+        vec![number(2), number(0)],
+        vec![number(4), number(1)],
+        vec![number(5), number(2)],
+      ]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "with recursive numbers(n) as (select 1 union all select n + 1 from numbers)
+        select n from numbers where n % 2 = 0 limit 3;"
+      ),
+      vec![vec![number(2)], vec![number(4)], vec![number(6)]]
+    );
+    // UNION stops once it only finds rows it has already seen
+    assert_eq!(
+      run_query(
+        &mut database,
+        "with recursive cycle(n) as (select 0 union select (n + 1) % 3 from cycle)
+        select count(*) from cycle;"
+      ),
+      vec![vec![number(3)]]
+    );
+    for query in &[
+      "with a as (select * from a) select * from a;",
+      "with a(x, y) as (select 1) select * from a;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_conditionals() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, score integer, nickname varchar(5));
+      insert into users (id, score, nickname) values (1, 10, 'a'), (2, 0, 'b'), (3, 30, 'c');",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select id, case when score > 20 then 2 when score > 0 then 1 else 0 end,
+          case id when 1 then score when 2 then -1 end, coalesce(nullif(score, 0), -5),
+          nullif(score, 10), iif(nickname = 'b', 1, 0)
+        from users order by id;"
+      ),
+      vec![
+        vec![
+          number(1),
+          number(1),
+          number(10),
+          number(10),
+          schema::OwnedRowCell::Null,
+          number(0)
+        ],
+        vec![
+          number(2),
+          number(0),
+          number(-1),
+          number(-5),
+          number(0),
+          number(1)
+        ],
+        vec![
+          number(3),
+          number(2),
+          schema::OwnedRowCell::Null,
+          number(30),
+          number(30),
+          number(0)
+        ],
+      ]
+    );
+    for query in &[
+      "select case when id > 1 then nickname else 0 end from users;",
+      "select coalesce(nickname, score) from users;",
+      "select nullif(score) from users;",
+      "select iif(1, 2) from users;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_conditionals_mixed_widths() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table t (a integer(4), b integer(8));
+      insert into t (a, b) values (1, 2), (1, 3), (-1, 3);",
+    );
+    // each branch comes out as wide as the widest one
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select count(*), min(case when a > 0 then a else b end) from t
+        group by case when a > 0 then a else b end order by 1;"
+      ),
+      vec![vec![number(1), number(3)], vec![number(2), number(1)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select coalesce(a, b) from t group by coalesce(a, b) order by 1;"
+      ),
+      vec![vec![number(-1)], vec![number(1)]]
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select a from t order by iif(a > 0, a, b), b;"
+      ),
+      vec![
+        vec![schema::OwnedRowCell::Number { value: 1, size: 4 }],
+        vec![schema::OwnedRowCell::Number { value: 1, size: 4 }],
+        vec![schema::OwnedRowCell::Number { value: -1, size: 4 }],
+      ]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_functions() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(10));
+      insert into users (id, name) values (1, 'xalicex'), (-2, 'bob');",
+    );
+    // upper() leaves room for characters that get longer
+    let upper = |value: &str| schema::OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 30,
+    };
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select abs(id), upper(trim(name, 'x')), length(name) from users
+        where substr(name, 1, 1) <> 'x' or abs(id) = 1 order by length(name);"
+      ),
+      vec![
+        vec![number(2), upper("BOB"), number(3)],
+        vec![number(1), upper("ALICE"), number(7)],
+      ]
+    );
+    assert!(database
+      .execute_query("select upper(id) from users;", |_| ())
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_blob_functions() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table files (id integer, data blob(4), name varchar(8));
+      insert into files (id, data, name) values (1, x'01020304', '0aff');",
+    );
+    let blob = |data: &[u8]| vec![schema::OwnedRowCell::Blob(data.to_vec())];
+    assert_eq!(
+      run_query(&mut database, "select substr(x'0102', 1, 1);"),
+      vec![blob(&[1])]
+    );
+    assert_eq!(
+      run_query(&mut database, "select substr(data, -3, 2) from files;"),
+      vec![blob(&[2, 3])]
+    );
+    assert_eq!(
+      run_query(&mut database, "select unhex('0aff');"),
+      vec![blob(&[10, 255])]
+    );
+    // the size of these can't be known until the rows are read
+    for query in &[
+      "select substr(data, id) from files;",
+      "select unhex(name) from files;",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_string_literals() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table notes (id integer, body varchar(20));
+      insert into notes (id, body) values (1, 'hello world'), (2, 'it''s ☃'), (3, 'two
+lines');",
+    );
+    let string = |value: &str| schema::OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 20,
+    };
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select body, length(body) from notes where body <> 'hello' order by id;"
+      ),
+      vec![
+        vec![string("hello world"), number(11)],
+        vec![string("it's ☃"), number(6)],
+        vec![string("two\nlines"), number(9)],
+      ]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_quoted_idents() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      r#"create table "table" ("value" integer, `x` integer, 名前 integer, "Two Words" integer);
+      insert into `table` ("value", "x", 名前, "Two Words") value (1, 2, 3, 4);"#,
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        r#"select t."value", t.`x`, 名前, "Two Words" from "table" as t;"#
+      ),
+      vec![vec![number(1), number(2), number(3), number(4)]]
+    );
+    // x is only special right before a string, where it starts a blob
+    run_query(
+      &mut database,
+      "create table t (x integer, data blob(1));
+      insert into t (x, data) values (1, x'01');",
+    );
+    assert_eq!(
+      run_query(
+        &mut database,
+        "select x, x.x, data from t as x where x = 1;"
+      ),
+      vec![vec![
+        number(1),
+        number(1),
+        schema::OwnedRowCell::Blob(vec![1])
+      ]]
+    );
+    // unquoted names are the same whatever their case, quoted ones aren't
+    run_query(
+      &mut database,
+      r#"create table Mixed (Id integer, "Id" integer);
+      insert into MIXED (ID, "Id") values (1, 2);"#,
+    );
+    assert_eq!(
+      run_query(&mut database, r#"select id, "Id" from mixed;"#),
+      vec![vec![number(1), number(2)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_prepared_statements() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table users (id integer, name varchar(20));",
+    );
+    let string = |value: &str| schema::OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 20,
+    };
+    let insert = database
+      .prepare("insert into users (id, name) values (?, ?2);")
+      .unwrap();
+    assert_eq!(insert.parameter_count(), 2);
+    for (id, name) in &[(1, "O'Brien"), (2, "two words")] {
+      database
+        .execute_prepared(&insert, &[number(*id), string(name)], |_| ())
+        .unwrap();
+    }
+
+    let select = database
+      .prepare("select name from users where id >= :min and id <= :min + ? limit ?3;")
+      .unwrap();
+    assert_eq!(select.parameter_count(), 3);
+    assert_eq!(select.parameter_index(":min"), Some(1));
+    let mut rows = vec![];
+    database
+      .execute_prepared(&select, &[number(1), number(5), number(10)], |row| {
+        rows.extend(row)
+      })
+      .unwrap();
+    assert_eq!(
+      rows,
+      vec![vec![string("O'Brien")], vec![string("two words")]]
+    );
+
+    assert!(matches!(
+      database.execute_prepared(&select, &[number(1)], |_| ()),
+      Err(DatabaseQueryError::InternalError(_))
+    ));
+    assert!(database
+      .execute_query("select name from users where id = ?;", |_| ())
+      .is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_statements_and_errors_outlive_query() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    let (insert, err) = {
+      let query =
+        String::from("create table users (id integer); insert into users (id) values (?);");
+      let err = database
+        .execute_query(&query.replace("integer", "integr"), |_| ())
+        .unwrap_err();
+      (database.prepare(&query).unwrap(), err)
+    };
+    match err {
+      DatabaseQueryError::AstError(parser::AstError::ParseError(err)) => {
+        assert_eq!(
+          err.line(),
+          "create table users (id integr); insert into users (id) values (?);"
+        )
+      }
+      err => panic!("{:?}", err),
+    }
+    database
+      .execute_prepared(&insert, &[number(1)], |_| ())
+      .unwrap();
+    assert_eq!(
+      run_query(&mut database, "select id from users;"),
+      vec![vec![number(1)]]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn test_insert_invalid_values() -> Result<(), DatabaseError> {
+    let mut database = Database::new(io::Cursor::new(vec![]))?;
+    run_query(
+      &mut database,
+      "create table items (id integer(1), name varchar(3), data blob(1));",
+    );
+    for query in &[
+      "insert into items (id, name, data) value (NULL, 'a', x'00');",
+      "insert into items (id, name, data) value (128, 'a', x'00');",
+      "insert into items (id, name, data) value (1, 'abcd', x'00');",
+      "insert into items (id, name, data) value (1, 'a', x'0000');",
+    ] {
+      assert!(database.execute_query(query, |_| ()).is_err());
+    }
+    Ok(())
+  }
 }
@@ -0,0 +1,1096 @@
+mod function;
+
+use self::function::ScalarFunction;
+use crate::table::{coerce, RowReader, Table, TableError, TableField};
+use crate::DatabaseError;
+use parser::{BinaryOperator, UnaryOperator};
+use schema::{Field, FieldKind, OwnedRowCell, RowCell};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+/// An expression from the ast that has been checked against the schema
+/// of the rows it's going to be evaluated on.
+///
+/// Column references are resolved to the index of the column up front,
+/// so evaluating an expression never has to go looking for names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+  Literal(OwnedRowCell),
+  Column(usize),
+  Unary {
+    op: UnaryOperator,
+    expr: Box<Expression>,
+  },
+  Binary {
+    op: BinaryOperator,
+    lhs: Box<Expression>,
+    rhs: Box<Expression>,
+  },
+  /// A column of the row that the query around a correlated subquery is on
+  Outer {
+    row: OuterRow,
+    idx: usize,
+    kind: FieldKind,
+  },
+  /// (SELECT ...), which is the first row of the subquery, or NULL
+  Subquery(Subquery),
+  /// EXISTS (SELECT ...)
+  Exists(Subquery),
+  /// expr [NOT] IN (SELECT ...)
+  InSubquery {
+    expr: Box<Expression>,
+    not: bool,
+    subquery: Subquery,
+  },
+  /// expr [NOT] IN (1, 2, 3)
+  InList {
+    expr: Box<Expression>,
+    not: bool,
+    list: Vec<Expression>,
+  },
+  /// CASE, and IIF(), which is a CASE with one WHEN
+  Case {
+    operand: Option<Box<Expression>>,
+    when_then: Vec<(Expression, Expression)>,
+    else_expr: Option<Box<Expression>>,
+    kind: FieldKind,
+  },
+  /// COALESCE(a, b, ...), the first argument that isn't NULL
+  Coalesce {
+    args: Vec<Expression>,
+    kind: FieldKind,
+  },
+  /// NULLIF(a, b), which is a unless it's equal to b
+  NullIf(Box<Expression>, Box<Expression>),
+  /// upper(name), length(data)
+  Function {
+    function: ScalarFunction,
+    args: Vec<Expression>,
+    kind: FieldKind,
+  },
+}
+
+/// The row of an outer query, as seen by the subqueries inside of it
+pub type OuterRow = Rc<RefCell<Vec<OwnedRowCell>>>;
+
+type SubqueryRows = Rc<Vec<Vec<OwnedRowCell>>>;
+
+/// A select inside of an expression. It's planned once, and then read
+/// again every time the expression needs its rows.
+#[derive(Clone)]
+pub struct Subquery {
+  table: Rc<RefCell<Box<dyn Table>>>,
+  /// Where the row of the outer query goes, for correlated subqueries
+  outer_row: Option<OuterRow>,
+  /// A subquery that doesn't refer to the outer query always has the same
+  /// rows, so they only get read once
+  rows: Rc<RefCell<Option<SubqueryRows>>>,
+}
+
+impl Subquery {
+  pub fn new(table: Box<dyn Table>, outer_row: Option<OuterRow>) -> Subquery {
+    Subquery {
+      table: Rc::new(RefCell::new(table)),
+      outer_row,
+      rows: Default::default(),
+    }
+  }
+
+  pub fn schema(&self) -> Vec<TableField> {
+    self.table.borrow().schema()
+  }
+
+  /// Runs the subquery for `row`, which is the current row of the outer
+  /// query. At most `limit` rows are read when the subquery is correlated.
+  fn rows(
+    &self,
+    row: &[RowCell],
+    disk: &mut dyn RowReader,
+    limit: Option<usize>,
+  ) -> Result<SubqueryRows, TableError> {
+    if let Some(rows) = &*self.rows.borrow() {
+      return Ok(rows.clone());
+    }
+    let mut table = self.table.borrow_mut();
+    if let Some(outer_row) = &self.outer_row {
+      *outer_row.borrow_mut() = row.iter().cloned().map(OwnedRowCell::from).collect();
+      table.rewind();
+    }
+    // the rows of a subquery that isn't correlated are kept for next time,
+    // so all of them have to be read
+    let limit = if self.outer_row.is_some() {
+      limit
+    } else {
+      None
+    };
+    let schema = table.schema();
+    let mut rows = vec![];
+    while limit.is_none_or(|limit| rows.len() < limit) {
+      match table.next_row(disk)? {
+        Some(row) => rows.push(row.into_cells(&schema)?),
+        None => break,
+      }
+    }
+    let rows = Rc::new(rows);
+    if self.outer_row.is_none() {
+      *self.rows.borrow_mut() = Some(rows.clone());
+    }
+    Ok(rows)
+  }
+}
+
+impl fmt::Debug for Subquery {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Subquery")
+      .field("schema", &self.schema())
+      .field("correlated", &self.outer_row.is_some())
+      .finish()
+  }
+}
+
+impl PartialEq for Subquery {
+  fn eq(&self, other: &Subquery) -> bool {
+    Rc::ptr_eq(&self.table, &other.table)
+  }
+}
+
+impl Expression {
+  pub fn compile(ast: &parser::Expr, fields: &[TableField]) -> Result<Expression, DatabaseError> {
+    Expression::compile_with(ast, fields, &|_| None)
+  }
+
+  /// Compiles an expression, but any part of the ast that `substitute`
+  /// returns an expression for is replaced with that expression.
+  ///
+  /// This is used to point aggregate function calls at the columns that
+  /// hold their results.
+  pub fn compile_with(
+    ast: &parser::Expr,
+    fields: &[TableField],
+    substitute: &dyn Fn(&parser::Expr) -> Option<Expression>,
+  ) -> Result<Expression, DatabaseError> {
+    use parser::Expr;
+    if let Some(expression) = substitute(ast) {
+      return Ok(expression);
+    }
+    match ast {
+      Expr::LiteralValue(_) => OwnedRowCell::from_ast_expr(ast)
+        .map(Expression::Literal)
+        .ok_or_else(|| DatabaseError::Other("Error: Invalid literal value".to_string())),
+      Expr::ColumnIdent(column_ident) => match resolve_column(column_ident, fields)? {
+        Some(idx) => Ok(Expression::Column(idx)),
+        None => Err(DatabaseError::Other(format!(
+          "Error: Could not find column {} in table",
+          column_ident
+        ))),
+      },
+      Expr::Unary { op, expr } => Ok(Expression::Unary {
+        op: *op,
+        expr: Box::new(Expression::compile_with(expr, fields, substitute)?),
+      }),
+      Expr::Binary { op, lhs, rhs } => Ok(Expression::Binary {
+        op: *op,
+        lhs: Box::new(Expression::compile_with(lhs, fields, substitute)?),
+        rhs: Box::new(Expression::compile_with(rhs, fields, substitute)?),
+      }),
+      Expr::FunctionCall(call) => compile_function(call, fields, substitute),
+      Expr::Case {
+        operand,
+        when_then,
+        else_expr,
+      } => {
+        let compile = |expr: &parser::Expr| Expression::compile_with(expr, fields, substitute);
+        let operand = match operand {
+          Some(operand) => Some(Box::new(compile(operand)?)),
+          None => None,
+        };
+        let when_then = when_then
+          .iter()
+          .map(|(when, then)| Ok((compile(when)?, compile(then)?)))
+          .collect::<Result<Vec<_>, DatabaseError>>()?;
+        let else_expr = match else_expr {
+          Some(else_expr) => Some(Box::new(compile(else_expr)?)),
+          None => None,
+        };
+        let results = when_then
+          .iter()
+          .map(|(_, then)| then)
+          .chain(else_expr.as_deref());
+        Ok(Expression::Case {
+          kind: result_kind(results, fields, "CASE")?,
+          operand,
+          when_then,
+          else_expr,
+        })
+      }
+      Expr::In {
+        expr,
+        not,
+        list: parser::InList::Exprs(list),
+      } => Ok(Expression::InList {
+        expr: Box::new(Expression::compile_with(expr, fields, substitute)?),
+        not: *not,
+        list: list
+          .iter()
+          .map(|item| Expression::compile_with(item, fields, substitute))
+          .collect::<Result<_, _>>()?,
+      }),
+      // subqueries have to be planned by the database, which passes them
+      // in through `substitute`
+      Expr::In { .. } | Expr::Exists(_) | Expr::Subquery(_) => Err(DatabaseError::Other(
+        "Error: Subqueries are not supported here".to_string(),
+      )),
+      // so do the values of parameters
+      Expr::Parameter(parameter) => Err(DatabaseError::Other(format!(
+        "Error: Parameter {} is not supported here",
+        parameter.text
+      ))),
+    }
+  }
+
+  /// The kind of value this expression produces, when evaluated against
+  /// rows with the given fields
+  pub fn kind(&self, fields: &[TableField]) -> FieldKind {
+    match self {
+      Expression::Literal(value) => match value {
+        OwnedRowCell::Number { size, .. } => FieldKind::Number(*size),
+        OwnedRowCell::Str { max_size, .. } => FieldKind::Str(*max_size),
+        OwnedRowCell::Blob(data) => FieldKind::Blob(data.len() as u64),
+        OwnedRowCell::Null => FieldKind::Number(8),
+      },
+      Expression::Column(idx) => fields[*idx].kind().clone(),
+      Expression::Outer { kind, .. } => kind.clone(),
+      Expression::Subquery(subquery) => subquery.schema()[0].kind().clone(),
+      Expression::Case { kind, .. }
+      | Expression::Coalesce { kind, .. }
+      | Expression::Function { kind, .. } => kind.clone(),
+      Expression::NullIf(lhs, _) => lhs.kind(fields),
+      Expression::Unary {
+        op: UnaryOperator::Plus,
+        expr,
+      } => expr.kind(fields),
+      // Everything else is either arithmetic or a boolean, and those are
+      // always full size numbers
+      Expression::Unary { .. }
+      | Expression::Binary { .. }
+      | Expression::Exists(_)
+      | Expression::InSubquery { .. }
+      | Expression::InList { .. } => FieldKind::Number(8),
+    }
+  }
+
+  /// Evaluates the expression for `row`. Subqueries read their rows
+  /// from `disk`.
+  pub fn eval(
+    &self,
+    row: &[RowCell],
+    disk: &mut dyn RowReader,
+  ) -> Result<OwnedRowCell, TableError> {
+    match self {
+      Expression::Literal(value) => Ok(value.clone()),
+      Expression::Column(idx) => match row.get(*idx) {
+        Some(cell) => Ok(cell.clone().into()),
+        None => Err(TableError::Other(format!(
+          "Column {} is out of bounds for a row with {} columns",
+          idx,
+          row.len()
+        ))),
+      },
+      Expression::Outer { row, idx, .. } => Ok(row.borrow()[*idx].clone()),
+      Expression::Unary { op, expr } => {
+        let value = expr.eval(row, disk)?;
+        match op {
+          UnaryOperator::Plus => Ok(value),
+          UnaryOperator::Negate => match as_number(&value) {
+            Some(n) => n.checked_neg().map(number).ok_or_else(overflow),
+            None => Ok(OwnedRowCell::Null),
+          },
+          UnaryOperator::Not => Ok(from_bool(as_bool(&value).map(|value| !value))),
+        }
+      }
+      Expression::Binary { op, lhs, rhs } => match op {
+        // AND and OR can skip the right hand side
+        BinaryOperator::And => match as_bool(&lhs.eval(row, disk)?) {
+          Some(false) => Ok(from_bool(Some(false))),
+          lhs => Ok(match (lhs, as_bool(&rhs.eval(row, disk)?)) {
+            (_, Some(false)) => from_bool(Some(false)),
+            (Some(true), Some(true)) => from_bool(Some(true)),
+            _ => OwnedRowCell::Null,
+          }),
+        },
+        BinaryOperator::Or => match as_bool(&lhs.eval(row, disk)?) {
+          Some(true) => Ok(from_bool(Some(true))),
+          lhs => Ok(match (lhs, as_bool(&rhs.eval(row, disk)?)) {
+            (_, Some(true)) => from_bool(Some(true)),
+            (Some(false), Some(false)) => from_bool(Some(false)),
+            _ => OwnedRowCell::Null,
+          }),
+        },
+        op => eval_binary(*op, lhs.eval(row, disk)?, rhs.eval(row, disk)?),
+      },
+      Expression::Subquery(subquery) => Ok(match subquery.rows(row, disk, Some(1))?.first() {
+        Some(first) => first[0].clone(),
+        None => OwnedRowCell::Null,
+      }),
+      Expression::Exists(subquery) => Ok(from_bool(Some(
+        !subquery.rows(row, disk, Some(1))?.is_empty(),
+      ))),
+      Expression::InSubquery {
+        expr,
+        not,
+        subquery,
+      } => {
+        let value = expr.eval(row, disk)?;
+        let rows = subquery.rows(row, disk, None)?;
+        let result = is_in(&value, rows.iter().map(|row| &row[0]));
+        Ok(from_bool(if *not {
+          result.map(|result| !result)
+        } else {
+          result
+        }))
+      }
+      Expression::InList { expr, not, list } => {
+        let value = expr.eval(row, disk)?;
+        let list = list
+          .iter()
+          .map(|item| item.eval(row, disk))
+          .collect::<Result<Vec<_>, _>>()?;
+        let result = is_in(&value, list.iter());
+        Ok(from_bool(if *not {
+          result.map(|result| !result)
+        } else {
+          result
+        }))
+      }
+      Expression::Case {
+        operand,
+        when_then,
+        else_expr,
+        kind,
+      } => {
+        let operand = match operand {
+          Some(operand) => Some(operand.eval(row, disk)?),
+          None => None,
+        };
+        // every branch comes out as wide as the widest one, so that the
+        // value always matches `kind`
+        let result = |value| coerce(value, &TableField::new(None, kind.clone()));
+        for (when, then) in when_then.iter() {
+          let when = when.eval(row, disk)?;
+          let is_match = match &operand {
+            Some(operand) => compare(operand, &when) == Some(Ordering::Equal),
+            None => as_bool(&when) == Some(true),
+          };
+          if is_match {
+            return result(then.eval(row, disk)?);
+          }
+        }
+        match else_expr {
+          Some(else_expr) => result(else_expr.eval(row, disk)?),
+          None => Ok(OwnedRowCell::Null),
+        }
+      }
+      Expression::Coalesce { args, kind } => {
+        for arg in args.iter() {
+          let value = arg.eval(row, disk)?;
+          if value != OwnedRowCell::Null {
+            return coerce(value, &TableField::new(None, kind.clone()));
+          }
+        }
+        Ok(OwnedRowCell::Null)
+      }
+      Expression::Function {
+        function,
+        args,
+        kind,
+      } => {
+        let args = args
+          .iter()
+          .map(|arg| arg.eval(row, disk))
+          .collect::<Result<Vec<_>, _>>()?;
+        function.eval(&args, kind)
+      }
+      Expression::NullIf(lhs, rhs) => {
+        let lhs = lhs.eval(row, disk)?;
+        let rhs = rhs.eval(row, disk)?;
+        if compare(&lhs, &rhs) == Some(Ordering::Equal) {
+          Ok(OwnedRowCell::Null)
+        } else {
+          Ok(lhs)
+        }
+      }
+    }
+  }
+}
+
+/// Compiles a call to a function that isn't an aggregate. The conditional
+/// functions don't always evaluate all of their arguments, so they get
+/// expressions of their own instead of being a `ScalarFunction`.
+fn compile_function(
+  call: &parser::FunctionCall,
+  fields: &[TableField],
+  substitute: &dyn Fn(&parser::Expr) -> Option<Expression>,
+) -> Result<Expression, DatabaseError> {
+  use parser::FunctionArgs;
+
+  let name = call.name.text();
+  // aggregates that made it this far aren't somewhere they can be used
+  if AggregateFunction::from_name(name).is_some() {
+    return Err(DatabaseError::Other(format!(
+      "Error: Misuse of aggregate function {}()",
+      name
+    )));
+  }
+  let function = name.to_lowercase();
+  let arity = match function.as_str() {
+    "coalesce" => 2..=usize::MAX,
+    "nullif" => 2..=2,
+    "iif" => 3..=3,
+    _ => match ScalarFunction::from_name(name) {
+      Some(function) => function.arity(),
+      None => {
+        return Err(DatabaseError::Other(format!(
+          "Error: No such function {}",
+          name
+        )))
+      }
+    },
+  };
+  let mut args = match &call.args {
+    FunctionArgs::Exprs(args) if arity.contains(&args.len()) => args
+      .iter()
+      .map(|arg| Expression::compile_with(arg, fields, substitute))
+      .collect::<Result<Vec<_>, _>>()?,
+    _ => {
+      return Err(DatabaseError::Other(format!(
+        "Error: Wrong number of arguments to function {}()",
+        name
+      )))
+    }
+  };
+
+  Ok(match function.as_str() {
+    "coalesce" => Expression::Coalesce {
+      kind: result_kind(args.iter(), fields, "coalesce()")?,
+      args,
+    },
+    "nullif" => {
+      let rhs = args.pop().unwrap();
+      let lhs = args.pop().unwrap();
+      Expression::NullIf(Box::new(lhs), Box::new(rhs))
+    }
+    "iif" => {
+      let else_expr = args.pop().unwrap();
+      let then = args.pop().unwrap();
+      let condition = args.pop().unwrap();
+      Expression::Case {
+        kind: result_kind(vec![&then, &else_expr], fields, "iif()")?,
+        operand: None,
+        when_then: vec![(condition, then)],
+        else_expr: Some(Box::new(else_expr)),
+      }
+    }
+    _ => {
+      let function = ScalarFunction::from_name(name).unwrap();
+      Expression::Function {
+        kind: function.kind(&args, fields)?,
+        function,
+        args,
+      }
+    }
+  })
+}
+
+/// The kind of a column that can hold the value of any of `results`.
+/// NULL fits anywhere, but otherwise they all have to be the same type of value.
+fn result_kind<'e>(
+  results: impl IntoIterator<Item = &'e Expression>,
+  fields: &[TableField],
+  what: &str,
+) -> Result<FieldKind, DatabaseError> {
+  let mut kind = None;
+  for result in results {
+    if *result == Expression::Literal(OwnedRowCell::Null) {
+      continue;
+    }
+    let next = result.kind(fields);
+    kind = Some(match kind {
+      Some(kind) => common_kind(&kind, &next).ok_or_else(|| {
+        DatabaseError::Other(format!(
+          "Error: The results of {} have different types",
+          what
+        ))
+      })?,
+      None => next,
+    });
+  }
+  Ok(kind.unwrap_or(FieldKind::Number(8)))
+}
+
+/// The smallest kind that can hold the values of both `lhs` and `rhs`, if
+/// they hold the same type of value
+pub fn common_kind(lhs: &FieldKind, rhs: &FieldKind) -> Option<FieldKind> {
+  use std::cmp::max;
+  match (lhs, rhs) {
+    (FieldKind::Number(a), FieldKind::Number(b)) => Some(FieldKind::Number(*max(a, b))),
+    (FieldKind::Str(a), FieldKind::Str(b)) => Some(FieldKind::Str(*max(a, b))),
+    (FieldKind::Blob(a), FieldKind::Blob(b)) => Some(FieldKind::Blob(*max(a, b))),
+    _ => None,
+  }
+}
+
+/// Finds the column that `column_ident` refers to, or `None` if there
+/// isn't one
+pub fn resolve_column(
+  column_ident: &parser::ColumnIdent,
+  fields: &[TableField],
+) -> Result<Option<usize>, DatabaseError> {
+  let mut matches = fields.iter().enumerate().filter(|(_, field)| {
+    field.name() == Some(column_ident.column.text())
+      && match &column_ident.table {
+        Some(table) => field.table() == Some(table.text()),
+        None => true,
+      }
+  });
+  match (matches.next(), matches.next()) {
+    (Some((idx, _)), None) => Ok(Some(idx)),
+    (Some(_), Some(_)) => Err(DatabaseError::Other(format!(
+      "Error: Column {} is ambiguous",
+      column_ident
+    ))),
+    (None, _) => Ok(None),
+  }
+}
+
+/// `value IN (list)`. Like `=`, this is NULL instead of false when either
+/// side is NULL and there's no match.
+fn is_in<'a>(value: &OwnedRowCell, list: impl Iterator<Item = &'a OwnedRowCell>) -> Option<bool> {
+  let mut result = Some(false);
+  for item in list {
+    match compare(value, item) {
+      Some(Ordering::Equal) => return Some(true),
+      Some(_) => {}
+      None => result = None,
+    }
+  }
+  result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+  Count,
+  Sum,
+  Min,
+  Max,
+  /// There aren't any floating point numbers, so the average is rounded
+  /// towards zero
+  Avg,
+}
+
+impl AggregateFunction {
+  pub fn from_name(name: &str) -> Option<AggregateFunction> {
+    match name.to_lowercase().as_str() {
+      "count" => Some(AggregateFunction::Count),
+      "sum" => Some(AggregateFunction::Sum),
+      "min" => Some(AggregateFunction::Min),
+      "max" => Some(AggregateFunction::Max),
+      "avg" => Some(AggregateFunction::Avg),
+      _ => None,
+    }
+  }
+}
+
+/// A call to an aggregate function, that's computed over every row in a group
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateCall {
+  pub function: AggregateFunction,
+  /// `None` for `count(*)`
+  pub arg: Option<Expression>,
+}
+
+impl AggregateCall {
+  pub fn compile(
+    call: &parser::FunctionCall,
+    fields: &[TableField],
+  ) -> Result<Option<AggregateCall>, DatabaseError> {
+    use parser::FunctionArgs;
+    let function = match AggregateFunction::from_name(call.name.text()) {
+      Some(function) => function,
+      None => return Ok(None),
+    };
+    let arg = match (&call.args, function) {
+      (FunctionArgs::Asterisk, AggregateFunction::Count) => None,
+      (FunctionArgs::Exprs(args), _) if args.len() == 1 => {
+        Some(Expression::compile(&args[0], fields)?)
+      }
+      _ => {
+        return Err(DatabaseError::Other(format!(
+          "Error: Wrong number of arguments to function {}()",
+          call.name.text()
+        )))
+      }
+    };
+    Ok(Some(AggregateCall { function, arg }))
+  }
+
+  /// The kind of value this aggregate produces, when it's computed over
+  /// rows with the given fields
+  pub fn kind(&self, fields: &[TableField]) -> FieldKind {
+    match (self.function, &self.arg) {
+      (AggregateFunction::Min, Some(arg)) | (AggregateFunction::Max, Some(arg)) => arg.kind(fields),
+      _ => FieldKind::Number(8),
+    }
+  }
+
+  pub fn accumulator(&self) -> Accumulator {
+    match self.function {
+      AggregateFunction::Count => Accumulator::Count(0),
+      AggregateFunction::Sum => Accumulator::Sum(None),
+      AggregateFunction::Min => Accumulator::Min(None),
+      AggregateFunction::Max => Accumulator::Max(None),
+      AggregateFunction::Avg => Accumulator::Avg { sum: 0, count: 0 },
+    }
+  }
+}
+
+/// Finds every call to an aggregate function in `ast`, skipping any that
+/// are already in `aggregates`
+pub fn collect_aggregates<'a>(
+  ast: &'a parser::Expr,
+  aggregates: &mut Vec<&'a parser::FunctionCall>,
+) {
+  use parser::{Expr, FunctionArgs};
+  match ast {
+    Expr::FunctionCall(call) if AggregateFunction::from_name(call.name.text()).is_some() => {
+      if !aggregates.contains(&call) {
+        aggregates.push(call);
+      }
+    }
+    Expr::FunctionCall(call) => {
+      if let FunctionArgs::Exprs(args) = &call.args {
+        for arg in args.iter() {
+          collect_aggregates(arg, aggregates);
+        }
+      }
+    }
+    Expr::Unary { expr, .. } => collect_aggregates(expr, aggregates),
+    Expr::Binary { lhs, rhs, .. } => {
+      collect_aggregates(lhs, aggregates);
+      collect_aggregates(rhs, aggregates);
+    }
+    Expr::In { expr, list, .. } => {
+      collect_aggregates(expr, aggregates);
+      if let parser::InList::Exprs(list) = list {
+        for item in list.iter() {
+          collect_aggregates(item, aggregates);
+        }
+      }
+    }
+    Expr::Case {
+      operand,
+      when_then,
+      else_expr,
+    } => {
+      let exprs = operand
+        .iter()
+        .map(|operand| &**operand)
+        .chain(when_then.iter().flat_map(|(when, then)| vec![when, then]))
+        .chain(else_expr.iter().map(|else_expr| &**else_expr));
+      for expr in exprs {
+        collect_aggregates(expr, aggregates);
+      }
+    }
+    // any aggregates inside a subquery belong to the subquery
+    Expr::LiteralValue(_)
+    | Expr::ColumnIdent(_)
+    | Expr::Parameter(_)
+    | Expr::Exists(_)
+    | Expr::Subquery(_) => {}
+  }
+}
+
+/// The running state of an aggregate for one group. NULLs are skipped by
+/// every aggregate, except for `count(*)`.
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+  Count(i64),
+  Sum(Option<i64>),
+  Min(Option<OwnedRowCell>),
+  Max(Option<OwnedRowCell>),
+  Avg { sum: i64, count: i64 },
+}
+
+impl Accumulator {
+  pub fn update(
+    &mut self,
+    call: &AggregateCall,
+    row: &[RowCell],
+    disk: &mut dyn RowReader,
+  ) -> Result<(), TableError> {
+    let value = match &call.arg {
+      Some(arg) => arg.eval(row, disk)?,
+      // count(*) counts every row, so it just needs something that isn't NULL
+      None => number(1),
+    };
+    if value == OwnedRowCell::Null {
+      return Ok(());
+    }
+    match self {
+      Accumulator::Count(count) => *count += 1,
+      Accumulator::Sum(sum) => {
+        let value = as_number(&value).unwrap_or(0);
+        *sum = Some(sum.unwrap_or(0).checked_add(value).ok_or_else(overflow)?);
+      }
+      Accumulator::Min(min) => {
+        if min
+          .as_ref()
+          .is_none_or(|min| sort_order(&value, min) == Ordering::Less)
+        {
+          *min = Some(value);
+        }
+      }
+      Accumulator::Max(max) => {
+        if max
+          .as_ref()
+          .is_none_or(|max| sort_order(&value, max) == Ordering::Greater)
+        {
+          *max = Some(value);
+        }
+      }
+      Accumulator::Avg { sum, count } => {
+        *sum = sum
+          .checked_add(as_number(&value).unwrap_or(0))
+          .ok_or_else(overflow)?;
+        *count += 1;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn finish(self) -> OwnedRowCell {
+    match self {
+      Accumulator::Count(count) => number(count),
+      Accumulator::Sum(sum) => sum.map(number).unwrap_or(OwnedRowCell::Null),
+      Accumulator::Min(value) | Accumulator::Max(value) => value.unwrap_or(OwnedRowCell::Null),
+      Accumulator::Avg { count: 0, .. } => OwnedRowCell::Null,
+      Accumulator::Avg { sum, count } => number(sum / count),
+    }
+  }
+}
+
+fn eval_binary(
+  op: BinaryOperator,
+  lhs: OwnedRowCell,
+  rhs: OwnedRowCell,
+) -> Result<OwnedRowCell, TableError> {
+  use BinaryOperator::*;
+  match op {
+    Is => Ok(from_bool(Some(is_same(&lhs, &rhs)))),
+    IsNot => Ok(from_bool(Some(!is_same(&lhs, &rhs)))),
+    LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual | Equal | NotEqual => {
+      let ordering = compare(&lhs, &rhs);
+      Ok(from_bool(ordering.map(|ordering| match op {
+        LessThan => ordering == Ordering::Less,
+        LessThanOrEqual => ordering != Ordering::Greater,
+        GreaterThan => ordering == Ordering::Greater,
+        GreaterThanOrEqual => ordering != Ordering::Less,
+        Equal => ordering == Ordering::Equal,
+        _ => ordering != Ordering::Equal,
+      })))
+    }
+    Multiply | Divide | Modulo | Add | Subtract => {
+      let (lhs, rhs) = match (as_number(&lhs), as_number(&rhs)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return Ok(OwnedRowCell::Null),
+      };
+      let result = match op {
+        Multiply => lhs.checked_mul(rhs),
+        Add => lhs.checked_add(rhs),
+        Subtract => lhs.checked_sub(rhs),
+        // Dividing by zero is NULL, just like in sqlite
+        Divide | Modulo if rhs == 0 => return Ok(OwnedRowCell::Null),
+        Divide => lhs.checked_div(rhs),
+        _ => lhs.checked_rem(rhs),
+      };
+      result.map(number).ok_or_else(overflow)
+    }
+    And | Or => unreachable!("AND and OR are short circuited"),
+  }
+}
+
+fn number(value: i64) -> OwnedRowCell {
+  OwnedRowCell::Number { value, size: 8 }
+}
+
+fn overflow() -> TableError {
+  TableError::Other("Integer overflow".to_string())
+}
+
+fn from_bool(value: Option<bool>) -> OwnedRowCell {
+  match value {
+    Some(value) => number(value as i64),
+    None => OwnedRowCell::Null,
+  }
+}
+
+/// The numeric value of a cell, for doing arithmetic with.
+/// Strings that don't hold a number count as 0, like they do in sqlite.
+fn as_number(value: &OwnedRowCell) -> Option<i64> {
+  match value {
+    OwnedRowCell::Number { value, .. } => Some(*value),
+    OwnedRowCell::Str { value, .. } => Some(value.trim().parse::<i64>().unwrap_or(0)),
+    OwnedRowCell::Blob(_) => Some(0),
+    OwnedRowCell::Null => None,
+  }
+}
+
+/// Conditions are three-valued: NULL is neither true nor false
+fn as_bool(value: &OwnedRowCell) -> Option<bool> {
+  match value {
+    OwnedRowCell::Null => None,
+    value => Some(is_truthy(value)),
+  }
+}
+
+/// Compares two values, or returns `None` if either of them is NULL.
+///
+/// Values of different types are never equal: numbers sort before
+/// strings, which sort before blobs.
+fn compare(lhs: &OwnedRowCell, rhs: &OwnedRowCell) -> Option<Ordering> {
+  fn type_rank(value: &OwnedRowCell) -> u8 {
+    match value {
+      OwnedRowCell::Null => 0,
+      OwnedRowCell::Number { .. } => 1,
+      OwnedRowCell::Str { .. } => 2,
+      OwnedRowCell::Blob(_) => 3,
+    }
+  }
+  match (lhs, rhs) {
+    (OwnedRowCell::Null, _) | (_, OwnedRowCell::Null) => None,
+    (OwnedRowCell::Number { value: lhs, .. }, OwnedRowCell::Number { value: rhs, .. }) => {
+      Some(lhs.cmp(rhs))
+    }
+    (OwnedRowCell::Str { value: lhs, .. }, OwnedRowCell::Str { value: rhs, .. }) => {
+      Some(lhs.cmp(rhs))
+    }
+    (OwnedRowCell::Blob(lhs), OwnedRowCell::Blob(rhs)) => Some(lhs.cmp(rhs)),
+    (lhs, rhs) => Some(type_rank(lhs).cmp(&type_rank(rhs))),
+  }
+}
+
+/// The order that values are sorted in by `ORDER BY`.
+///
+/// This is the same as comparing them, except NULLs come before everything else.
+pub fn sort_order(lhs: &OwnedRowCell, rhs: &OwnedRowCell) -> Ordering {
+  match (lhs, rhs) {
+    (OwnedRowCell::Null, OwnedRowCell::Null) => Ordering::Equal,
+    (OwnedRowCell::Null, _) => Ordering::Less,
+    (_, OwnedRowCell::Null) => Ordering::Greater,
+    (lhs, rhs) => compare(lhs, rhs).unwrap_or(Ordering::Equal),
+  }
+}
+
+/// Equality where NULL is the same as NULL, for `IS` and `IS NOT`
+fn is_same(lhs: &OwnedRowCell, rhs: &OwnedRowCell) -> bool {
+  match (lhs, rhs) {
+    (OwnedRowCell::Null, OwnedRowCell::Null) => true,
+    (lhs, rhs) => compare(lhs, rhs) == Some(Ordering::Equal),
+  }
+}
+
+/// Whether a value counts as true when it's used as a condition.
+///
+/// Like sqlite, numbers are true when they're non-zero, and strings
+/// are true when they hold a non-zero number. NULL is never true.
+pub fn is_truthy(value: &OwnedRowCell) -> bool {
+  match value {
+    OwnedRowCell::Number { value, .. } => *value != 0,
+    OwnedRowCell::Str { value, .. } => value.trim().parse::<i64>().is_ok_and(|n| n != 0),
+    OwnedRowCell::Blob(_) | OwnedRowCell::Null => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::table::tests::NoDisk;
+
+  fn eval(query: &str) -> OwnedRowCell {
+    let query = format!("select {};", query);
+    let ast = match parser::process_query(&query).unwrap().remove(0) {
+      parser::Statement::Select(select) => select,
+      _ => unreachable!(),
+    };
+    let value = match &ast.core.columns[0] {
+      parser::ResultColumn::Expr { value, .. } => value,
+      _ => unreachable!(),
+    };
+    Expression::compile(value, &[])
+      .unwrap()
+      .eval(&[], &mut NoDisk)
+      .unwrap()
+  }
+
+  #[test]
+  fn test_arithmetic() {
+    assert_eq!(eval("1 + 2 * 3"), number(7));
+    assert_eq!(eval("(1 + 2) * 3"), number(9));
+    assert_eq!(eval("7 / 2 - -1"), number(4));
+    assert_eq!(eval("7 % 4"), number(3));
+    assert_eq!(eval("1 / 0"), OwnedRowCell::Null);
+    assert_eq!(eval("1 + NULL"), OwnedRowCell::Null);
+  }
+
+  #[test]
+  fn test_comparisons() {
+    assert_eq!(eval("1 < 2"), number(1));
+    assert_eq!(eval("'abc' = 'abc'"), number(1));
+    assert_eq!(eval("'abc' <> 'abd'"), number(1));
+    assert_eq!(eval("1 = '1'"), number(0));
+    assert_eq!(eval("1 < 'a'"), number(1));
+    assert_eq!(eval("NULL = NULL"), OwnedRowCell::Null);
+    assert_eq!(eval("NULL IS NULL"), number(1));
+    assert_eq!(eval("1 IS NOT NULL"), number(1));
+  }
+
+  #[test]
+  fn test_sort_order() {
+    let string = |value: &str| OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 10,
+    };
+    let mut values = vec![
+      string("b"),
+      number(2),
+      OwnedRowCell::Blob(vec![0]),
+      OwnedRowCell::Null,
+      string("a"),
+      number(-1),
+    ];
+    values.sort_by(sort_order);
+    assert_eq!(
+      values,
+      vec![
+        OwnedRowCell::Null,
+        number(-1),
+        number(2),
+        string("a"),
+        string("b"),
+        OwnedRowCell::Blob(vec![0]),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_conditionals() {
+    let string = |value: &str| OwnedRowCell::Str {
+      max_size: value.len() as u64,
+      value: value.to_string(),
+    };
+    assert_eq!(
+      eval("CASE WHEN 1 > 2 THEN 'a' WHEN 2 > 1 THEN 'bc' END"),
+      string("bc")
+    );
+    assert_eq!(eval("CASE WHEN NULL THEN 1 ELSE 2 END"), number(2));
+    assert_eq!(eval("CASE 2 WHEN 1 THEN 10 WHEN 2 THEN 20 END"), number(20));
+    assert_eq!(eval("CASE NULL WHEN NULL THEN 1 END"), OwnedRowCell::Null);
+    assert_eq!(eval("coalesce(NULL, NULL, 3, 4)"), number(3));
+    assert_eq!(eval("nullif(1, 1)"), OwnedRowCell::Null);
+    assert_eq!(eval("nullif(1, 2)"), number(1));
+    // the result is as wide as the widest branch, whichever one is picked
+    assert_eq!(
+      eval("iif(0, 'yes', 'no')"),
+      OwnedRowCell::Str {
+        value: "no".to_string(),
+        max_size: 3
+      }
+    );
+    assert_eq!(
+      eval("coalesce(x'01', x'0203')"),
+      OwnedRowCell::Blob(vec![1, 0])
+    );
+  }
+
+  #[test]
+  fn test_functions() {
+    let string = |value: &str, max_size: u64| OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size,
+    };
+    assert_eq!(eval("length('hello')"), number(5));
+    assert_eq!(eval("length(x'0102')"), number(2));
+    assert_eq!(eval("length(-12)"), number(3));
+    assert_eq!(eval("upper('abc')"), string("ABC", 9));
+    assert_eq!(eval("LOWER('ABC')"), string("abc", 9));
+    assert_eq!(eval("upper('ɐ')"), string("Ɐ", 6));
+    assert_eq!(eval("substr('hello', 2, 3)"), string("ell", 5));
+    assert_eq!(eval("substr('hello', -3)"), string("llo", 5));
+    assert_eq!(eval("substr('hello', 0, 2)"), string("h", 5));
+    assert_eq!(eval("substr('hello', 4, -2)"), string("el", 5));
+    assert_eq!(eval("substr(x'010203', 2)"), OwnedRowCell::Blob(vec![2, 3]));
+    assert_eq!(eval("trim('xxaxx', 'x')"), string("a", 5));
+    assert_eq!(eval("replace('abab', 'b', 'cd')"), string("acdacd", 8));
+    assert_eq!(eval("abs(-3)"), number(3));
+    assert_eq!(eval("round(3, 2)"), number(3));
+    assert_eq!(eval("hex(x'0aff')"), string("0AFF", 4));
+    assert_eq!(eval("hex(NULL)"), string("", 0));
+    assert_eq!(eval("unhex('0aff')"), OwnedRowCell::Blob(vec![10, 255]));
+    assert_eq!(eval("unhex('xyz')"), OwnedRowCell::Null);
+    assert_eq!(eval("typeof(NULL)"), string("null", 7));
+    assert_eq!(eval("typeof('a')"), string("text", 7));
+    assert_eq!(eval("upper(NULL)"), OwnedRowCell::Null);
+  }
+
+  #[test]
+  fn test_function_kinds() {
+    let fields = vec![
+      TableField::new(Some("id".to_string()), FieldKind::Number(4)),
+      TableField::new(Some("name".to_string()), FieldKind::Str(10)),
+      TableField::new(Some("data".to_string()), FieldKind::Blob(8)),
+    ];
+    let kind = |query: &str| {
+      let query = format!("select {};", query);
+      let ast = match parser::process_query(&query).unwrap().remove(0) {
+        parser::Statement::Select(select) => select,
+        _ => unreachable!(),
+      };
+      match &ast.core.columns[0] {
+        parser::ResultColumn::Expr { value, .. } => {
+          Expression::compile(value, &fields).map(|expr| expr.kind(&fields))
+        }
+        _ => unreachable!(),
+      }
+    };
+    assert_eq!(kind("length(name)").unwrap(), FieldKind::Number(8));
+    assert_eq!(kind("upper(name)").unwrap(), FieldKind::Str(30));
+    assert_eq!(kind("substr(data, 2)").unwrap(), FieldKind::Blob(7));
+    assert_eq!(kind("substr(data, -3, 2)").unwrap(), FieldKind::Blob(2));
+    assert_eq!(kind("hex(data)").unwrap(), FieldKind::Str(16));
+    assert_eq!(kind("unhex('0aff')").unwrap(), FieldKind::Blob(2));
+    assert_eq!(kind("abs(id)").unwrap(), FieldKind::Number(8));
+    for query in &[
+      "upper(id)",
+      "abs(name)",
+      "substr(name, name)",
+      "substr(data, id)",
+      "unhex(name)",
+      "replace(name, 'a')",
+      "length()",
+      "unknown(id)",
+    ] {
+      assert!(kind(query).is_err(), "{} should not compile", query);
+    }
+  }
+
+  #[test]
+  fn test_three_valued_logic() {
+    assert_eq!(eval("NULL AND 0"), number(0));
+    assert_eq!(eval("NULL AND 1"), OwnedRowCell::Null);
+    assert_eq!(eval("NULL OR 1"), number(1));
+    assert_eq!(eval("NULL OR 0"), OwnedRowCell::Null);
+    assert_eq!(eval("NOT NULL"), OwnedRowCell::Null);
+    assert_eq!(eval("NOT 1 = 2"), number(1));
+  }
+}
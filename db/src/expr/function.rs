@@ -0,0 +1,296 @@
+use super::{as_number, number, overflow, Expression};
+use crate::table::{TableError, TableField};
+use crate::DatabaseError;
+use schema::{FieldKind, OwnedRowCell};
+use std::ops::RangeInclusive;
+
+/// The built in functions that work on one row at a time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalarFunction {
+  Length,
+  Upper,
+  Lower,
+  Substr,
+  Trim,
+  Replace,
+  Abs,
+  /// There aren't any floating point numbers, so this never does anything
+  Round,
+  Hex,
+  Unhex,
+  TypeOf,
+}
+
+impl ScalarFunction {
+  pub fn from_name(name: &str) -> Option<ScalarFunction> {
+    match name.to_lowercase().as_str() {
+      "length" => Some(ScalarFunction::Length),
+      "upper" => Some(ScalarFunction::Upper),
+      "lower" => Some(ScalarFunction::Lower),
+      "substr" => Some(ScalarFunction::Substr),
+      "trim" => Some(ScalarFunction::Trim),
+      "replace" => Some(ScalarFunction::Replace),
+      "abs" => Some(ScalarFunction::Abs),
+      "round" => Some(ScalarFunction::Round),
+      "hex" => Some(ScalarFunction::Hex),
+      "unhex" => Some(ScalarFunction::Unhex),
+      "typeof" => Some(ScalarFunction::TypeOf),
+      _ => None,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ScalarFunction::Length => "length",
+      ScalarFunction::Upper => "upper",
+      ScalarFunction::Lower => "lower",
+      ScalarFunction::Substr => "substr",
+      ScalarFunction::Trim => "trim",
+      ScalarFunction::Replace => "replace",
+      ScalarFunction::Abs => "abs",
+      ScalarFunction::Round => "round",
+      ScalarFunction::Hex => "hex",
+      ScalarFunction::Unhex => "unhex",
+      ScalarFunction::TypeOf => "typeof",
+    }
+  }
+
+  /// How many arguments the function can be called with
+  pub fn arity(self) -> RangeInclusive<usize> {
+    match self {
+      ScalarFunction::Substr => 2..=3,
+      ScalarFunction::Trim | ScalarFunction::Round => 1..=2,
+      ScalarFunction::Replace => 3..=3,
+      _ => 1..=1,
+    }
+  }
+
+  /// Checks that `args` are the right kinds of values for the function,
+  /// and works out the kind of value it returns
+  pub fn kind(
+    self,
+    args: &[Expression],
+    fields: &[TableField],
+  ) -> Result<FieldKind, DatabaseError> {
+    let kinds = args
+      .iter()
+      .map(|arg| match arg {
+        Expression::Literal(OwnedRowCell::Null) => None,
+        arg => Some(arg.kind(fields)),
+      })
+      .collect::<Vec<_>>();
+    let type_error = |idx: usize, expected: &str| {
+      DatabaseError::Other(format!(
+        "Error: Argument {} of {}() must be a {}",
+        idx + 1,
+        self.name(),
+        expected
+      ))
+    };
+    // NULL can be passed in place of anything, and doesn't take up any space
+    let size_of = |idx: usize, expected: &str| match (&kinds[idx], expected) {
+      (None, _) => Ok(0),
+      (Some(FieldKind::Number(size)), "number") => Ok(u64::from(*size)),
+      (Some(FieldKind::Str(size)), "string") => Ok(*size),
+      _ => Err(type_error(idx, expected)),
+    };
+    let fixed_size_error = || {
+      DatabaseError::Other(format!(
+        "Error: The size of the blob returned by {}() has to be known up front, so its arguments must be literals",
+        self.name()
+      ))
+    };
+    let all_of = |expected: &str| {
+      (0..kinds.len())
+        .map(|idx| size_of(idx, expected))
+        .collect::<Result<Vec<_>, _>>()
+    };
+
+    Ok(match self {
+      ScalarFunction::Length => FieldKind::Number(8),
+      ScalarFunction::Abs | ScalarFunction::Round => {
+        all_of("number")?;
+        FieldKind::Number(8)
+      }
+      // changing the case of a character can make it take up to three
+      // times as many bytes, like "ΐ" becoming "Ϊ́"
+      ScalarFunction::Upper | ScalarFunction::Lower => {
+        FieldKind::Str(all_of("string")?[0].saturating_mul(3))
+      }
+      ScalarFunction::Trim => FieldKind::Str(all_of("string")?[0]),
+      ScalarFunction::Replace => {
+        let sizes = all_of("string")?;
+        // the worst case is every character being replaced
+        FieldKind::Str(sizes[0].saturating_mul(std::cmp::max(sizes[2], 1)))
+      }
+      ScalarFunction::Substr => {
+        for idx in 1..kinds.len() {
+          size_of(idx, "number")?;
+        }
+        match &kinds[0] {
+          // blobs are always their full size, so the result's size has to
+          // be worked out up front rather than being padded out to the input's
+          Some(FieldKind::Blob(size)) => {
+            let bounds = args[1..]
+              .iter()
+              .map(|arg| match arg {
+                Expression::Literal(value) => Ok(as_number(value).unwrap_or(0)),
+                _ => Err(fixed_size_error()),
+              })
+              .collect::<Result<Vec<_>, _>>()?;
+            let (start, end) = substr_range(*size as usize, bounds[0], bounds.get(1).cloned());
+            FieldKind::Blob((end - start) as u64)
+          }
+          _ => FieldKind::Str(size_of(0, "string")?),
+        }
+      }
+      ScalarFunction::Hex => FieldKind::Str(match &kinds[0] {
+        // the longest number is -9223372036854775808
+        Some(FieldKind::Number(_)) => 40,
+        Some(FieldKind::Str(size)) | Some(FieldKind::Blob(size)) => size.saturating_mul(2),
+        None => 0,
+      }),
+      ScalarFunction::Unhex => {
+        size_of(0, "string")?;
+        match &args[0] {
+          Expression::Literal(value) => {
+            FieldKind::Blob(hex::decode(as_text(value)).map_or(0, |data| data.len() as u64))
+          }
+          _ => return Err(fixed_size_error()),
+        }
+      }
+      ScalarFunction::TypeOf => FieldKind::Str(7),
+    })
+  }
+
+  /// Calls the function. Strings that it returns are given the max size
+  /// from `kind`.
+  pub fn eval(self, args: &[OwnedRowCell], kind: &FieldKind) -> Result<OwnedRowCell, TableError> {
+    let text = |value: String| OwnedRowCell::Str {
+      max_size: match kind {
+        FieldKind::Str(max_size) => *max_size,
+        _ => value.len() as u64,
+      },
+      value,
+    };
+    let string_arg = |idx: usize| as_text(&args[idx]);
+    let number_arg = |idx: usize| as_number(&args[idx]).unwrap_or(0);
+
+    match self {
+      ScalarFunction::TypeOf => {
+        let name = match &args[0] {
+          OwnedRowCell::Number { .. } => "integer",
+          OwnedRowCell::Str { .. } => "text",
+          OwnedRowCell::Blob(_) => "blob",
+          OwnedRowCell::Null => "null",
+        };
+        return Ok(text(name.to_string()));
+      }
+      // like in sqlite, this is the one function that doesn't turn NULL into NULL
+      ScalarFunction::Hex if args[0] == OwnedRowCell::Null => return Ok(text(String::new())),
+      _ if args.contains(&OwnedRowCell::Null) => return Ok(OwnedRowCell::Null),
+      _ => {}
+    }
+
+    Ok(match self {
+      ScalarFunction::Length => number(match &args[0] {
+        OwnedRowCell::Blob(data) => data.len() as i64,
+        value => as_text(value).chars().count() as i64,
+      }),
+      ScalarFunction::Upper => text(string_arg(0).to_uppercase()),
+      ScalarFunction::Lower => text(string_arg(0).to_lowercase()),
+      ScalarFunction::Trim => {
+        let value = string_arg(0);
+        let trimmed = match args.get(1) {
+          Some(chars) => {
+            let chars = as_text(chars);
+            value.trim_matches(|c| chars.contains(c))
+          }
+          None => value.trim_matches(' '),
+        };
+        text(trimmed.to_string())
+      }
+      ScalarFunction::Replace => {
+        let from = string_arg(1);
+        if from.is_empty() {
+          text(string_arg(0))
+        } else {
+          text(string_arg(0).replace(&from, &string_arg(2)))
+        }
+      }
+      ScalarFunction::Substr => {
+        let start = number_arg(1);
+        let len = args.get(2).map(|_| number_arg(2));
+        match &args[0] {
+          OwnedRowCell::Blob(data) => {
+            let (start, end) = substr_range(data.len(), start, len);
+            OwnedRowCell::Blob(data[start..end].to_vec())
+          }
+          value => {
+            let chars = as_text(value).chars().collect::<Vec<_>>();
+            let (start, end) = substr_range(chars.len(), start, len);
+            text(chars[start..end].iter().collect())
+          }
+        }
+      }
+      ScalarFunction::Abs => number(number_arg(0).checked_abs().ok_or_else(overflow)?),
+      ScalarFunction::Round => number(number_arg(0)),
+      ScalarFunction::Hex => text(match &args[0] {
+        OwnedRowCell::Blob(data) => hex::encode_upper(data),
+        value => hex::encode_upper(as_text(value)),
+      }),
+      ScalarFunction::Unhex => match hex::decode(string_arg(0)) {
+        Ok(data) => OwnedRowCell::Blob(data),
+        Err(_) => OwnedRowCell::Null,
+      },
+      ScalarFunction::TypeOf => unreachable!(),
+    })
+  }
+}
+
+/// The text of a value, for the functions that work on strings
+fn as_text(value: &OwnedRowCell) -> String {
+  match value {
+    OwnedRowCell::Number { value, .. } => value.to_string(),
+    OwnedRowCell::Str { value, .. } => value.clone(),
+    OwnedRowCell::Blob(data) => String::from_utf8_lossy(data).into_owned(),
+    OwnedRowCell::Null => String::new(),
+  }
+}
+
+/// The part of something `len` characters long that substr() returns, the
+/// same way sqlite works it out: `start` counts from 1, or from the end
+/// if it's negative, and a negative `count` takes the characters before
+/// `start` instead of after it.
+fn substr_range(len: usize, start: i64, count: Option<i64>) -> (usize, usize) {
+  let len = len as i64;
+  let mut start = start;
+  let mut count = count.unwrap_or(i64::MAX);
+  let backwards = count < 0;
+  if backwards {
+    count = count.saturating_neg();
+  }
+  if start < 0 {
+    start = start.saturating_add(len);
+    if start < 0 {
+      // only the part of the range that's inside the string counts
+      count = std::cmp::max(count.saturating_add(start), 0);
+      start = 0;
+    }
+  } else if start > 0 {
+    start -= 1;
+  } else if count > 0 {
+    // position 0 is just before the first character
+    count -= 1;
+  }
+  if backwards {
+    start -= count;
+    if start < 0 {
+      count += start;
+      start = 0;
+    }
+  }
+  let start = std::cmp::min(start, len);
+  let end = std::cmp::min(start.saturating_add(count), len);
+  (start as usize, end as usize)
+}
@@ -3,6 +3,7 @@
 mod block;
 mod blockdisk;
 mod database;
+mod expr;
 #[cfg(test)]
 mod inmemorydb;
 mod table;
@@ -10,4 +11,4 @@ mod table;
 use self::block::Block;
 use self::blockdisk::BlockDisk;
 
-pub use self::database::{Database, DatabaseError, DatabaseQueryError};
+pub use self::database::{Database, DatabaseError, DatabaseQueryError, PreparedStatement};
@@ -1,24 +1,124 @@
-use schema::{
-  Field, FieldKind, OnDiskSchema, OwnedRowCell, Row, RowCell, RowCellError, SchemaField,
-};
-use std::collections::BTreeMap;
+use crate::expr::{self, Accumulator, AggregateCall, Expression};
+use parser::{CompoundOperator, SortOrder};
+use schema::{Field, FieldKind, OnDiskSchema, OwnedRowCell, Row, RowCellError, SchemaField};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
 
 pub trait RowReader {
-  fn read_nth_row(&mut self, schema: &OnDiskSchema, index: u64) -> Result<Option<Row>, TableError>;
+  /// Reads the first row at or after `index` that hasn't been deleted,
+  /// along with the index that it was found at.
+  fn read_nth_row(
+    &mut self,
+    schema: &OnDiskSchema,
+    index: u64,
+  ) -> Result<Option<(u64, Row)>, TableError>;
 }
 
 pub trait Table {
   fn schema(&self) -> Vec<TableField>;
   fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError>;
-  fn map_schema(
+  /// Goes back to the first row, so the table can be read again. This is
+  /// how correlated subqueries get run once for every row of the outer query.
+  fn rewind(&mut self);
+  /// Computes the columns of `next_schema` from each row, using the
+  /// matching expression in `columns`
+  fn map_schema(self, next_schema: Vec<TableField>, columns: Vec<Expression>) -> MapSchema<Self>
+  where
+    Self: Sized,
+  {
+    MapSchema {
+      prev_schema: self.schema(),
+      schema: next_schema,
+      columns,
+      iter: self,
+    }
+  }
+  /// Puts every column in this table under `table`
+  fn qualify(self, table: &str) -> Qualify<Self>
+  where
+    Self: Sized,
+  {
+    Qualify {
+      schema: self
+        .schema()
+        .into_iter()
+        .map(|field| field.qualify(table))
+        .collect(),
+      iter: self,
+    }
+  }
+  /// Only yields the rows for which `predicate` is true
+  fn filter(self, predicate: Expression) -> Filter<Self>
+  where
+    Self: Sized,
+  {
+    Filter {
+      schema: self.schema(),
+      predicate,
+      iter: self,
+    }
+  }
+  /// Sorts all of the rows by `keys`, where the first key is the most
+  /// significant. Rows with equal keys stay in the order they came in.
+  fn sort(self, keys: Vec<(Expression, SortOrder)>) -> Sort<Self>
+  where
+    Self: Sized,
+  {
+    Sort {
+      schema: self.schema(),
+      keys,
+      iter: self,
+      rows: None,
+    }
+  }
+  /// Splits the rows into groups that have the same values for `group_by`,
+  /// and computes `aggregates` over each group.
+  ///
+  /// Each output row has the values of `group_by`, followed by the results
+  /// of `aggregates`, and the fields for those are passed in as `schema`.
+  fn aggregate(
     self,
-    next_schema: Vec<TableField>,
-    alias_mapping: BTreeMap<&str, &str>,
-  ) -> MapSchema<Self>
+    schema: Vec<TableField>,
+    group_by: Vec<Expression>,
+    aggregates: Vec<AggregateCall>,
+  ) -> Aggregate<Self>
+  where
+    Self: Sized,
+  {
+    Aggregate {
+      prev_schema: self.schema(),
+      schema,
+      group_by,
+      aggregates,
+      iter: self,
+      rows: None,
+    }
+  }
+  /// Skips any rows that have the same values as a row that came before them
+  fn distinct(self) -> Distinct<Self>
+  where
+    Self: Sized,
+  {
+    Distinct {
+      schema: self.schema(),
+      seen: Default::default(),
+      iter: self,
+    }
+  }
+  /// Skips the first `offset` rows, and then stops after `limit` rows
+  fn limit(self, limit: Option<u64>, offset: u64) -> Limit<Self>
   where
     Self: Sized,
   {
-    MapSchema::new(self.schema(), next_schema, alias_mapping, self)
+    Limit {
+      limit,
+      offset,
+      remaining_limit: limit,
+      remaining_offset: offset,
+      iter: self,
+    }
   }
   fn into_iter_cells<'a>(self, disk: &'a mut dyn RowReader) -> IntoIterCells<'a, Self>
   where
@@ -32,6 +132,18 @@ pub trait Table {
   }
 }
 
+impl<T: Table + ?Sized> Table for Box<T> {
+  fn schema(&self) -> Vec<TableField> {
+    (**self).schema()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    (**self).next_row(disk)
+  }
+  fn rewind(&mut self) {
+    (**self).rewind()
+  }
+}
+
 pub struct SchemaReader {
   schema: OnDiskSchema,
   current_row: u64,
@@ -48,56 +160,581 @@ impl SchemaReader {
 
 impl Table for SchemaReader {
   fn schema(&self) -> Vec<TableField> {
-    self
-      .schema
-      .schema()
-      .fields()
-      .iter()
-      .map(|schema_field| TableField::from(schema_field))
-      .collect()
+    table_fields(&self.schema)
   }
   fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
     let row = disk.read_nth_row(&self.schema, self.current_row)?;
     match row {
-      Some(row) => {
-        self.current_row += 1;
+      Some((index, row)) => {
+        // skip over any deleted rows that came before this one
+        self.current_row = index + 1;
         Ok(Some(row))
       }
       None => Ok(None),
     }
   }
+  fn rewind(&mut self) {
+    self.current_row = 0;
+  }
 }
 
-pub struct MultiTableIterator<A: Table, B: Table> {
-  tables: (A, B),
+/// How rows from the right side of a join are matched up with rows from
+/// the left side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+  /// Only rows that match the join constraint
+  Inner,
+  /// Every row from the left, padded with NULLs when nothing on the right matches
+  Left,
 }
 
-impl<A: Table, B: Table> Table for MultiTableIterator<A, B> {
+/// Joins every row from `left` with every row from `right` that the
+/// constraint is true for.
+///
+/// The rows on the right are read in once and kept in memory, since we
+/// go through them once for every row on the left.
+pub struct NestedLoopJoin<L, R> {
+  left: L,
+  right: R,
+  kind: JoinKind,
+  constraint: Option<Expression>,
+  left_schema: Vec<TableField>,
+  right_schema: Vec<TableField>,
+  schema: Vec<TableField>,
+  right_rows: Option<Vec<Vec<OwnedRowCell>>>,
+  current: Option<JoinState>,
+}
+
+struct JoinState {
+  left: Vec<OwnedRowCell>,
+  right_idx: usize,
+  has_matched: bool,
+}
+
+impl<L: Table, R: Table> NestedLoopJoin<L, R> {
+  pub fn new(left: L, right: R, kind: JoinKind, constraint: Option<Expression>) -> Self {
+    let left_schema = left.schema();
+    let right_schema = right.schema();
+    let schema = left_schema
+      .iter()
+      .chain(right_schema.iter())
+      .cloned()
+      .collect();
+    NestedLoopJoin {
+      left,
+      right,
+      kind,
+      constraint,
+      left_schema,
+      right_schema,
+      schema,
+      right_rows: None,
+      current: None,
+    }
+  }
+}
+
+impl<L: Table, R: Table> Table for NestedLoopJoin<L, R> {
   fn schema(&self) -> Vec<TableField> {
-    let mut buf = vec![];
-    buf.append(&mut self.tables.0.schema());
-    buf.append(&mut self.tables.1.schema());
-    buf
+    self.schema.to_vec()
   }
   fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
-    let row_a = self.tables.0.next_row(disk)?;
-    let row_b = self.tables.1.next_row(disk)?;
-    let row_a = match row_a {
-      Some(row) => row,
-      None => return Ok(None),
-    };
-    let row_b = match row_b {
-      Some(row) => row,
-      None => return Ok(None),
-    };
+    if self.right_rows.is_none() {
+      let mut rows = vec![];
+      while let Some(row) = self.right.next_row(disk)? {
+        rows.push(row.into_cells(&self.right_schema)?);
+      }
+      self.right_rows = Some(rows);
+    }
+    let right_rows = self.right_rows.as_ref().unwrap();
 
-    let mut row_a = row_a.into_data();
-    let mut row_b = row_b.into_data();
-    let mut buf = vec![];
-    buf.append(&mut row_a);
-    buf.append(&mut row_b);
+    loop {
+      let state = match &mut self.current {
+        Some(state) => state,
+        None => match self.left.next_row(disk)? {
+          Some(row) => self.current.get_or_insert(JoinState {
+            left: row.into_cells(&self.left_schema)?,
+            right_idx: 0,
+            has_matched: false,
+          }),
+          None => return Ok(None),
+        },
+      };
 
-    Ok(Some(Row::from_data(buf)))
+      while let Some(right) = right_rows.get(state.right_idx) {
+        state.right_idx += 1;
+        let cells: Vec<OwnedRowCell> = state.left.iter().chain(right.iter()).cloned().collect();
+        let is_match = match &self.constraint {
+          Some(constraint) => {
+            let row: Vec<_> = cells.iter().map(OwnedRowCell::as_rowcell).collect();
+            expr::is_truthy(&constraint.eval(&row, disk)?)
+          }
+          None => true,
+        };
+        if is_match {
+          state.has_matched = true;
+          return Ok(Some(Row::from_cells(cells)?));
+        }
+      }
+
+      let state = self.current.take().unwrap();
+      if self.kind == JoinKind::Left && !state.has_matched {
+        let mut cells = state.left;
+        cells.extend(self.right_schema.iter().map(|_| OwnedRowCell::Null));
+        return Ok(Some(Row::from_cells(cells)?));
+      }
+    }
+  }
+  fn rewind(&mut self) {
+    self.left.rewind();
+    self.right.rewind();
+    self.right_rows = None;
+    self.current = None;
+  }
+}
+
+/// Passes rows through untouched, but puts every column under a new
+/// table name. This is how `FROM users AS u` works.
+pub struct Qualify<I> {
+  schema: Vec<TableField>,
+  iter: I,
+}
+
+impl<I: Table> Table for Qualify<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    self.iter.next_row(disk)
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind()
+  }
+}
+
+pub struct Filter<I> {
+  schema: Vec<TableField>,
+  predicate: Expression,
+  iter: I,
+}
+
+impl<I: Table> Table for Filter<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    while let Some(row) = self.iter.next_row(disk)? {
+      let cells = row.as_cells(&self.schema)?;
+      if expr::is_truthy(&self.predicate.eval(&cells, disk)?) {
+        return Ok(Some(row));
+      }
+    }
+    Ok(None)
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind()
+  }
+}
+
+pub struct Sort<I> {
+  schema: Vec<TableField>,
+  keys: Vec<(Expression, SortOrder)>,
+  iter: I,
+  /// Sorting needs every row, so these get read in all at once
+  rows: Option<std::vec::IntoIter<Row>>,
+}
+
+impl<I: Table> Table for Sort<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    if self.rows.is_none() {
+      let mut rows = vec![];
+      while let Some(row) = self.iter.next_row(disk)? {
+        let cells = row.as_cells(&self.schema)?;
+        let keys = self
+          .keys
+          .iter()
+          .map(|(key, _)| key.eval(&cells, disk))
+          .collect::<Result<Vec<_>, _>>()?;
+        rows.push((keys, row));
+      }
+      let orders: Vec<SortOrder> = self.keys.iter().map(|(_, order)| *order).collect();
+      rows.sort_by(|(lhs, _), (rhs, _)| {
+        for ((lhs, rhs), order) in lhs.iter().zip(rhs.iter()).zip(orders.iter()) {
+          let ordering = match order {
+            SortOrder::Asc => expr::sort_order(lhs, rhs),
+            SortOrder::Desc => expr::sort_order(rhs, lhs),
+          };
+          if ordering != Ordering::Equal {
+            return ordering;
+          }
+        }
+        Ordering::Equal
+      });
+      self.rows = Some(
+        rows
+          .into_iter()
+          .map(|(_, row)| row)
+          .collect::<Vec<_>>()
+          .into_iter(),
+      );
+    }
+    Ok(self.rows.as_mut().unwrap().next())
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind();
+    self.rows = None;
+  }
+}
+
+pub struct Aggregate<I> {
+  prev_schema: Vec<TableField>,
+  schema: Vec<TableField>,
+  group_by: Vec<Expression>,
+  aggregates: Vec<AggregateCall>,
+  iter: I,
+  rows: Option<std::vec::IntoIter<Row>>,
+}
+
+impl<I: Table> Aggregate<I> {
+  fn compute(&mut self, disk: &mut dyn RowReader) -> Result<Vec<Row>, TableError> {
+    use std::collections::HashMap;
+
+    // groups come out in the order they were first seen
+    let mut group_indices: HashMap<Vec<OwnedRowCell>, usize> = HashMap::new();
+    let mut groups: Vec<(Vec<OwnedRowCell>, Vec<Accumulator>)> = vec![];
+    // without a GROUP BY everything is in one group, even if there aren't any rows
+    if self.group_by.is_empty() {
+      group_indices.insert(vec![], 0);
+      groups.push((vec![], self.accumulators()));
+    }
+
+    while let Some(row) = self.iter.next_row(disk)? {
+      let cells = row.as_cells(&self.prev_schema)?;
+      // the key is coerced up front, so that the same value at different
+      // widths still ends up in the same group
+      let key = self
+        .group_by
+        .iter()
+        .zip(self.schema.iter())
+        .map(|(expression, field)| coerce(expression.eval(&cells, disk)?, field))
+        .collect::<Result<Vec<_>, _>>()?;
+      let idx = match group_indices.get(&key) {
+        Some(idx) => *idx,
+        None => {
+          group_indices.insert(key.clone(), groups.len());
+          groups.push((key, self.accumulators()));
+          groups.len() - 1
+        }
+      };
+      let accumulators = &mut groups[idx].1;
+      for (accumulator, call) in accumulators.iter_mut().zip(self.aggregates.iter()) {
+        accumulator.update(call, &cells, disk)?;
+      }
+    }
+
+    let mut rows = Vec::with_capacity(groups.len());
+    let aggregate_fields = &self.schema[self.group_by.len()..];
+    for (mut cells, accumulators) in groups {
+      for (accumulator, field) in accumulators.into_iter().zip(aggregate_fields) {
+        cells.push(coerce(accumulator.finish(), field)?);
+      }
+      rows.push(Row::from_cells(cells)?);
+    }
+    Ok(rows)
+  }
+  fn accumulators(&self) -> Vec<Accumulator> {
+    self
+      .aggregates
+      .iter()
+      .map(AggregateCall::accumulator)
+      .collect()
+  }
+}
+
+impl<I: Table> Table for Aggregate<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    if self.rows.is_none() {
+      self.rows = Some(self.compute(disk)?.into_iter());
+    }
+    Ok(self.rows.as_mut().unwrap().next())
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind();
+    self.rows = None;
+  }
+}
+
+pub struct Distinct<I> {
+  schema: Vec<TableField>,
+  seen: HashSet<Vec<OwnedRowCell>>,
+  iter: I,
+}
+
+impl<I: Table> Table for Distinct<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    while let Some(row) = self.iter.next_row(disk)? {
+      let cells = row.clone().into_cells(&self.schema)?;
+      if self.seen.insert(cells) {
+        return Ok(Some(row));
+      }
+    }
+    Ok(None)
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind();
+    self.seen.clear();
+  }
+}
+
+/// Combines the rows of two tables with the same schema, for
+/// UNION, UNION ALL, INTERSECT and EXCEPT
+pub struct Compound<L, R> {
+  left: L,
+  right: R,
+  operator: CompoundOperator,
+  schema: Vec<TableField>,
+  left_done: bool,
+  seen: HashSet<Vec<OwnedRowCell>>,
+  /// Every row of the right side, for INTERSECT and EXCEPT
+  right_rows: Option<HashSet<Vec<OwnedRowCell>>>,
+}
+
+impl<L: Table, R: Table> Compound<L, R> {
+  pub fn new(left: L, right: R, operator: CompoundOperator) -> Self {
+    Compound {
+      schema: left.schema(),
+      left,
+      right,
+      operator,
+      left_done: false,
+      seen: Default::default(),
+      right_rows: None,
+    }
+  }
+}
+
+impl<L: Table, R: Table> Table for Compound<L, R> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    match self.operator {
+      CompoundOperator::Union | CompoundOperator::UnionAll => loop {
+        let row = if self.left_done {
+          self.right.next_row(disk)?
+        } else {
+          match self.left.next_row(disk)? {
+            Some(row) => Some(row),
+            None => {
+              self.left_done = true;
+              continue;
+            }
+          }
+        };
+        let row = match row {
+          Some(row) => row,
+          None => return Ok(None),
+        };
+        if self.operator == CompoundOperator::UnionAll
+          || self.seen.insert(row.clone().into_cells(&self.schema)?)
+        {
+          return Ok(Some(row));
+        }
+      },
+      CompoundOperator::Intersect | CompoundOperator::Except => {
+        if self.right_rows.is_none() {
+          let mut right_rows = HashSet::new();
+          while let Some(row) = self.right.next_row(disk)? {
+            right_rows.insert(row.into_cells(&self.schema)?);
+          }
+          self.right_rows = Some(right_rows);
+        }
+        let keep_matches = self.operator == CompoundOperator::Intersect;
+        while let Some(row) = self.left.next_row(disk)? {
+          let cells = row.clone().into_cells(&self.schema)?;
+          let matches = self.right_rows.as_ref().unwrap().contains(&cells);
+          if matches == keep_matches && self.seen.insert(cells) {
+            return Ok(Some(row));
+          }
+        }
+        Ok(None)
+      }
+    }
+  }
+  fn rewind(&mut self) {
+    self.left.rewind();
+    self.right.rewind();
+    self.left_done = false;
+    self.seen.clear();
+    self.right_rows = None;
+  }
+}
+
+/// The rows of a recursive common table expression.
+///
+/// The rows from `initial` come out first. Every row that comes out gets
+/// queued up, and once it's at the front of the queue it's put in `current`
+/// (where the working table of `recursive` reads it from), and `recursive`
+/// runs again to find the rows that come after it.
+pub struct RecursiveCte<I, R> {
+  initial: I,
+  recursive: R,
+  schema: Vec<TableField>,
+  current: Rc<RefCell<Option<Row>>>,
+  /// UNION ALL keeps every row, UNION skips rows that have already come out
+  union_all: bool,
+  queue: VecDeque<Row>,
+  seen: HashSet<Vec<OwnedRowCell>>,
+  initial_done: bool,
+  /// Whether `recursive` is running for the row in `current`
+  recursing: bool,
+}
+
+impl<I: Table, R: Table> RecursiveCte<I, R> {
+  pub fn new(initial: I, recursive: R, current: Rc<RefCell<Option<Row>>>, union_all: bool) -> Self {
+    RecursiveCte {
+      schema: initial.schema(),
+      initial,
+      recursive,
+      current,
+      union_all,
+      queue: VecDeque::new(),
+      seen: Default::default(),
+      initial_done: false,
+      recursing: false,
+    }
+  }
+}
+
+impl<I: Table, R: Table> Table for RecursiveCte<I, R> {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    loop {
+      let row = if !self.initial_done {
+        self.initial.next_row(disk)?
+      } else if self.recursing {
+        self.recursive.next_row(disk)?
+      } else {
+        match self.queue.pop_front() {
+          Some(row) => {
+            *self.current.borrow_mut() = Some(row);
+            self.recursive.rewind();
+            self.recursing = true;
+            continue;
+          }
+          None => return Ok(None),
+        }
+      };
+      let row = match row {
+        Some(row) => row,
+        None => {
+          if self.initial_done {
+            self.recursing = false;
+          }
+          self.initial_done = true;
+          continue;
+        }
+      };
+      if !self.union_all && !self.seen.insert(row.clone().into_cells(&self.schema)?) {
+        continue;
+      }
+      self.queue.push_back(row.clone());
+      return Ok(Some(row));
+    }
+  }
+  fn rewind(&mut self) {
+    self.initial.rewind();
+    self.recursive.rewind();
+    *self.current.borrow_mut() = None;
+    self.queue.clear();
+    self.seen.clear();
+    self.initial_done = false;
+    self.recursing = false;
+  }
+}
+
+/// What the recursive part of a recursive common table expression reads
+/// from when it refers to itself: a table with just the row in `row`
+pub struct WorkingTable {
+  schema: Vec<TableField>,
+  row: Rc<RefCell<Option<Row>>>,
+  done: bool,
+}
+
+impl WorkingTable {
+  pub fn new(schema: Vec<TableField>, row: Rc<RefCell<Option<Row>>>) -> WorkingTable {
+    WorkingTable {
+      schema,
+      row,
+      done: false,
+    }
+  }
+}
+
+impl Table for WorkingTable {
+  fn schema(&self) -> Vec<TableField> {
+    self.schema.to_vec()
+  }
+  fn next_row(&mut self, _disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    if self.done {
+      return Ok(None);
+    }
+    self.done = true;
+    Ok(self.row.borrow().clone())
+  }
+  fn rewind(&mut self) {
+    self.done = false;
+  }
+}
+
+pub struct Limit<I> {
+  limit: Option<u64>,
+  offset: u64,
+  /// How many more rows can be read, or `None` if there's no limit
+  remaining_limit: Option<u64>,
+  /// How many more rows need to be skipped
+  remaining_offset: u64,
+  iter: I,
+}
+
+impl<I: Table> Table for Limit<I> {
+  fn schema(&self) -> Vec<TableField> {
+    self.iter.schema()
+  }
+  fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    // once we hit the limit we stop reading, so the rest of the
+    // table never gets touched
+    if self.remaining_limit == Some(0) {
+      return Ok(None);
+    }
+    while self.remaining_offset > 0 {
+      if self.iter.next_row(disk)?.is_none() {
+        self.remaining_limit = Some(0);
+        return Ok(None);
+      }
+      self.remaining_offset -= 1;
+    }
+    let row = self.iter.next_row(disk)?;
+    if let Some(limit) = &mut self.remaining_limit {
+      *limit = if row.is_some() { *limit - 1 } else { 0 };
+    }
+    Ok(row)
+  }
+  fn rewind(&mut self) {
+    self.iter.rewind();
+    self.remaining_limit = self.limit;
+    self.remaining_offset = self.offset;
   }
 }
 
@@ -119,95 +756,69 @@ impl<'a, I: Table> Iterator for IntoIterCells<'a, I> {
   }
 }
 
+/// Projects each row into a new set of columns, where every column in the
+/// new schema is computed by an expression over the previous row
 pub struct MapSchema<I> {
-  prev_schema_lookup: BTreeMap<String, (TableField, usize)>,
+  prev_schema: Vec<TableField>,
   schema: Vec<TableField>,
+  columns: Vec<Expression>,
   iter: I,
 }
 
-impl<I: Table> MapSchema<I> {
-  fn new(
-    prev_schema: Vec<TableField>,
-    schema: Vec<TableField>,
-    alias_mapping: BTreeMap<&str, &str>,
-    iter: I,
-  ) -> Self {
-    let prev_schema_lookup = {
-      let mut table: BTreeMap<String, (TableField, usize)> = BTreeMap::default();
-      let mut offset = 0;
-      for column in prev_schema.into_iter() {
-        let size = column.kind().size();
-        // ok so alias_mapping goes original_column -> alias_name
-        // prev_schema is the actual, physical table itself.
-        if let Some(name) = &column.name {
-          let real_name = alias_mapping
-            .get(name.as_str())
-            .map(|string| string.to_string())
-            .unwrap_or_else(|| name.clone());
-          table.insert(real_name, (column, offset));
-        }
-        offset += size;
-      }
-      table
-    };
-    MapSchema {
-      prev_schema_lookup,
-      schema,
-      iter,
-    }
-  }
-}
-
 impl<I> Table for MapSchema<I>
 where
   I: Table,
 {
   fn next_row(&mut self, disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
-    let row = self.iter.next_row(disk)?;
-    let row = match row {
+    let row = match self.iter.next_row(disk)? {
       Some(row) => row,
       None => return Ok(None),
     };
+    let cells = row.as_cells(&self.prev_schema)?;
     let mut next_row: Vec<OwnedRowCell> = Vec::with_capacity(self.schema.len());
-    for column in self.schema.iter() {
-      match &column.literal_value {
-        Some(literal) => {
-          let row_val = match literal {
-            TableFieldLiteral::Blob(data) => OwnedRowCell::Blob(data.clone()),
-            TableFieldLiteral::Str(data) => OwnedRowCell::Str {
-              value: data.clone(),
-              max_size: data.len() as u64,
-            },
-            TableFieldLiteral::Number(value) => OwnedRowCell::Number {
-              value: *value,
-              size: 8,
-            },
-          };
-          // TODO :: This unwrap should be safe, but we need to
-          // make the types better
-          let row_val = row_val.coerce_to(column).unwrap();
-          next_row.push(row_val);
-          continue;
-        }
-        None => {}
-      };
-      let column_name = match &column.name {
-        Some(name) => name,
-        None => return Err(TableError::Other(format!("Invalid schema"))),
-      };
-      match self.prev_schema_lookup.get(column_name.as_str()) {
-        Some((prev_column, offset)) => {
-          let data = RowCell::new(row.data(), prev_column, *offset)?;
-          next_row.push(data.into());
-        }
-        None => return Err(TableError::Other(format!("Invalid schema"))),
-      };
+    for (column, field) in self.columns.iter().zip(self.schema.iter()) {
+      next_row.push(coerce(column.eval(&cells, disk)?, field)?);
     }
     Ok(Some(Row::from_cells(next_row)?))
   }
   fn schema(&self) -> Vec<TableField> {
     self.schema.to_vec()
   }
+  fn rewind(&mut self) {
+    self.iter.rewind()
+  }
+}
+
+/// Makes a value the width that `field` says it is, so that it can be
+/// put in a row with that schema
+pub fn coerce(value: OwnedRowCell, field: &TableField) -> Result<OwnedRowCell, TableError> {
+  value
+    .coerce_to(field)
+    .map_err(|err| TableError::Other(format!("{} {}", err, field.name().unwrap_or("<unnamed>"))))
+}
+
+/// A table with exactly one row, that has no columns.
+///
+/// This is what a `SELECT` without a `FROM` reads from.
+#[derive(Default)]
+pub struct SingleRow {
+  done: bool,
+}
+
+impl Table for SingleRow {
+  fn schema(&self) -> Vec<TableField> {
+    vec![]
+  }
+  fn next_row(&mut self, _disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+    if self.done {
+      return Ok(None);
+    }
+    self.done = true;
+    Ok(Some(Row::from_cells(vec![])?))
+  }
+  fn rewind(&mut self) {
+    self.done = false;
+  }
 }
 
 #[derive(Debug)]
@@ -229,18 +840,12 @@ impl From<std::io::Error> for TableError {
   }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum TableFieldLiteral {
-  Number(i64),
-  Str(String),
-  Blob(Vec<u8>),
-}
-
 #[derive(Debug, PartialEq, Clone)]
 pub struct TableField {
+  /// The table this column came from, so that it can be referred to as `table.column`
+  table: Option<String>,
   name: Option<String>,
   kind: FieldKind,
-  literal_value: Option<TableFieldLiteral>,
 }
 
 impl TableField {
@@ -250,17 +855,36 @@ impl TableField {
       None => None,
     }
   }
-  pub fn new(
-    name: Option<String>,
-    kind: FieldKind,
-    literal_value: Option<TableFieldLiteral>,
-  ) -> TableField {
+  pub fn table(&self) -> Option<&str> {
+    match &self.table {
+      Some(table) => Some(table.as_str()),
+      None => None,
+    }
+  }
+  pub fn new(name: Option<String>, kind: FieldKind) -> TableField {
     TableField {
+      table: None,
       name,
       kind,
-      literal_value,
     }
   }
+  pub fn qualify(self, table: &str) -> TableField {
+    TableField {
+      table: Some(table.to_string()),
+      ..self
+    }
+  }
+}
+
+/// The fields of a table on the disk, qualified with the name of the table
+pub fn table_fields(table: &OnDiskSchema) -> Vec<TableField> {
+  let name = table.schema().name();
+  table
+    .schema()
+    .fields()
+    .iter()
+    .map(|field| TableField::from(field).qualify(name))
+    .collect()
 }
 
 impl Field for TableField {
@@ -272,9 +896,59 @@ impl Field for TableField {
 impl<'a> From<&'a SchemaField> for TableField {
   fn from(field: &'a SchemaField) -> TableField {
     TableField {
+      table: None,
       name: Some(field.name().to_string()),
       kind: field.kind().clone(),
-      literal_value: None,
     }
   }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  pub(crate) struct NoDisk;
+  impl RowReader for NoDisk {
+    fn read_nth_row(
+      &mut self,
+      _schema: &OnDiskSchema,
+      _index: u64,
+    ) -> Result<Option<(u64, Row)>, TableError> {
+      Ok(None)
+    }
+  }
+
+  /// Counts up from 0 forever
+  #[derive(Default)]
+  struct Counter {
+    next: i64,
+  }
+  impl Table for Counter {
+    fn schema(&self) -> Vec<TableField> {
+      vec![TableField::new(Some("n".to_string()), FieldKind::Number(8))]
+    }
+    fn next_row(&mut self, _disk: &mut dyn RowReader) -> Result<Option<Row>, TableError> {
+      self.next += 1;
+      Ok(Some(Row::from_cells(vec![OwnedRowCell::Number {
+        value: self.next - 1,
+        size: 8,
+      }])?))
+    }
+    fn rewind(&mut self) {
+      self.next = 0;
+    }
+  }
+
+  #[test]
+  fn test_limit_stops_reading() -> Result<(), TableError> {
+    let rows = Counter::default()
+      .limit(Some(3), 2)
+      .into_iter_cells(&mut NoDisk)
+      .collect::<Result<Vec<_>, _>>()?;
+    let numbers: Vec<Vec<OwnedRowCell>> = (2..5)
+      .map(|value| vec![OwnedRowCell::Number { value, size: 8 }])
+      .collect();
+    assert_eq!(rows, numbers);
+    Ok(())
+  }
+}
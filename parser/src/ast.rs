@@ -1,115 +1,1105 @@
+use crate::lang::Sql;
+use crate::tokenizer::Language;
 use std::fmt;
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Ident<'a>(&'a str);
+pub struct Ident {
+  text: String,
+  /// Whether the identifier was written in quotes, e.g. "user name"
+  quoted: bool,
+}
 
-impl<'a> Ident<'a> {
-  pub fn new(text: &'a str) -> Self {
-    Ident(text)
+impl Ident {
+  /// Unquoted identifiers aren't case sensitive, so they're folded to
+  /// lowercase. Quoted ones keep their case.
+  pub fn new(text: &str) -> Self {
+    Ident {
+      text: text.to_lowercase(),
+      quoted: false,
+    }
+  }
+  pub fn quoted(text: &str) -> Self {
+    Ident {
+      text: text.to_string(),
+      quoted: true,
+    }
+  }
+  pub fn text(&self) -> &str {
+    &self.text
   }
-  pub fn text(&self) -> &'a str {
-    self.0
+  pub fn is_quoted(&self) -> bool {
+    self.quoted
+  }
+
+  /// Whether the identifier can be written without quotes
+  fn is_bare(&self) -> bool {
+    static KEYWORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    let keywords =
+      KEYWORDS.get_or_init(|| Sql::keywords().iter().map(|keyword| keyword.text).collect());
+    let mut chars = self.text.chars();
+    let starts_word = match chars.next() {
+      Some(c) => c.is_alphabetic() || c == '_',
+      None => false,
+    };
+    starts_word
+      && chars.all(|c| c.is_alphanumeric() || c == '_')
+      && self.text == self.text.to_lowercase()
+      && !keywords.contains(&self.text.as_str())
   }
 }
 
-impl<'a> fmt::Display for Ident<'a> {
+impl fmt::Display for Ident {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "\"{}\"", self.text())
+    if self.quoted || !self.is_bare() {
+      write!(f, "\"{}\"", self.text.replace('"', "\"\""))
+    } else {
+      write!(f, "{}", self.text)
+    }
+  }
+}
+
+/// Writes `items` separated by commas
+fn comma_separated<T: fmt::Display>(
+  f: &mut fmt::Formatter,
+  items: impl IntoIterator<Item = T>,
+) -> fmt::Result {
+  for (idx, item) in items.into_iter().enumerate() {
+    if idx > 0 {
+      write!(f, ", ")?;
+    }
+    write!(f, "{}", item)?;
   }
+  Ok(())
 }
 
+/*
+ * Statements display as SQL that parses back to the same statement. The
+ * alternate flag (`{:#}`) puts each clause of a statement on its own line,
+ * which is what `relatable fmt` uses.
+ */
+
+// Statements are only ever parsed a handful at a time, so there's no
+// point boxing the big ones
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq)]
-pub enum Statement<'a> {
-  CreateTable(CreateTableStatement<'a>),
-  Select(SelectStatement<'a>),
-  Insert(InsertStatement<'a>),
+pub enum Statement {
+  CreateTable(CreateTableStatement),
+  Select(SelectStatement),
+  Insert(InsertStatement),
+  Delete(DeleteStatement),
+  Update(UpdateStatement),
+  DropTable(DropTableStatement),
+  AlterTable(AlterTableStatement),
+}
+
+impl fmt::Display for Statement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let statement: &dyn fmt::Display = match self {
+      Statement::CreateTable(statement) => statement,
+      Statement::Select(statement) => statement,
+      Statement::Insert(statement) => statement,
+      Statement::Delete(statement) => statement,
+      Statement::Update(statement) => statement,
+      Statement::DropTable(statement) => statement,
+      Statement::AlterTable(statement) => statement,
+    };
+    if f.alternate() {
+      write!(f, "{:#}", statement)
+    } else {
+      write!(f, "{}", statement)
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct CreateTableStatement<'a> {
-  pub table_name: Ident<'a>,
-  pub column_defs: Vec<ColumnDef<'a>>,
+pub struct CreateTableStatement {
+  pub table_name: Ident,
+  pub column_defs: Vec<ColumnDef>,
 }
 
+impl fmt::Display for CreateTableStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "CREATE TABLE {} (", self.table_name)?;
+    if f.alternate() {
+      for (idx, column_def) in self.column_defs.iter().enumerate() {
+        let separator = if idx > 0 { "," } else { "" };
+        write!(f, "{}\n  {}", separator, column_def)?;
+      }
+      write!(f, "\n)")
+    } else {
+      comma_separated(f, &self.column_defs)?;
+      write!(f, ")")
+    }
+  }
+}
+
+/// DROP TABLE [IF EXISTS] users
 #[derive(Debug, Clone, PartialEq)]
-pub struct ColumnDef<'a> {
-  pub column_name: Ident<'a>,
+pub struct DropTableStatement {
+  pub table_name: Ident,
+  pub if_exists: bool,
+}
+
+impl fmt::Display for DropTableStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "DROP TABLE ")?;
+    if self.if_exists {
+      write!(f, "IF EXISTS ")?;
+    }
+    write!(f, "{}", self.table_name)
+  }
+}
+
+/// ALTER TABLE users <action>
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlterTableStatement {
+  pub table_name: Ident,
+  pub action: AlterTableAction,
+}
+
+impl fmt::Display for AlterTableStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "ALTER TABLE {} {}", self.table_name, self.action)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterTableAction {
+  /// ADD [COLUMN] email VARCHAR(100)
+  AddColumn(ColumnDef),
+  /// RENAME [COLUMN] email TO contact
+  RenameColumn { from: Ident, to: Ident },
+  /// RENAME TO people
+  RenameTable(Ident),
+}
+
+impl fmt::Display for AlterTableAction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AlterTableAction::AddColumn(column_def) => write!(f, "ADD COLUMN {}", column_def),
+      AlterTableAction::RenameColumn { from, to } => {
+        write!(f, "RENAME COLUMN {} TO {}", from, to)
+      }
+      AlterTableAction::RenameTable(to) => write!(f, "RENAME TO {}", to),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+  pub column_name: Ident,
   pub type_name: TypeName,
 }
 
+impl fmt::Display for ColumnDef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {}", self.column_name, self.type_name)
+  }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TypeName {
   pub name: Type,
   pub argument: Option<i64>,
 }
 
+impl fmt::Display for TypeName {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name)?;
+    if let Some(argument) = self.argument {
+      write!(f, "({})", argument)?;
+    }
+    Ok(())
+  }
+}
+
+/// The names a column's type can be given. Several of them mean the same
+/// thing, but they're kept apart so that they display the way they were
+/// written.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
+  /// INTEGER(n), an n byte integer
   Integer,
+  /// INT, a 4 byte integer
+  Int,
+  /// SMALLINT, a 2 byte integer
+  SmallInt,
+  /// BIGINT, an 8 byte integer
+  BigInt,
   Blob,
   Varchar,
+  /// CHAR(n), the same as VARCHAR(n)
+  Char,
+  /// TEXT(n), the same as VARCHAR(n)
+  Text,
+}
+
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Type::Integer => write!(f, "INTEGER"),
+      Type::Int => write!(f, "INT"),
+      Type::SmallInt => write!(f, "SMALLINT"),
+      Type::BigInt => write!(f, "BIGINT"),
+      Type::Blob => write!(f, "BLOB"),
+      Type::Varchar => write!(f, "VARCHAR"),
+      Type::Char => write!(f, "CHAR"),
+      Type::Text => write!(f, "TEXT"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+  /// WITH reports AS (SELECT ...) SELECT * FROM reports
+  pub with: Option<WithClause>,
+  pub core: SelectCore,
+  /// SELECT ... UNION SELECT ...
+  pub compound: Vec<CompoundSelect>,
+  /// SELECT * FROM users ORDER BY name, id DESC
+  pub order_by: Vec<OrderingTerm>,
+  /// SELECT * FROM users LIMIT 10 OFFSET 20
+  pub limit: Option<Limit>,
+}
+
+impl fmt::Display for SelectStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let separator = if f.alternate() { "\n" } else { " " };
+    if let Some(with) = &self.with {
+      write!(f, "{}{}", with, separator)?;
+    }
+    if f.alternate() {
+      write!(f, "{:#}", self.core)?;
+    } else {
+      write!(f, "{}", self.core)?;
+    }
+    for compound in self.compound.iter() {
+      write!(f, "{}{}{}", separator, compound.operator, separator)?;
+      if f.alternate() {
+        write!(f, "{:#}", compound.core)?;
+      } else {
+        write!(f, "{}", compound.core)?;
+      }
+    }
+    if !self.order_by.is_empty() {
+      write!(f, "{}ORDER BY ", separator)?;
+      comma_separated(f, &self.order_by)?;
+    }
+    if let Some(limit) = &self.limit {
+      write!(f, "{}{}", separator, limit)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithClause {
+  /// WITH RECURSIVE
+  pub recursive: bool,
+  pub ctes: Vec<CommonTableExpression>,
+}
+
+impl fmt::Display for WithClause {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "WITH ")?;
+    if self.recursive {
+      write!(f, "RECURSIVE ")?;
+    }
+    comma_separated(f, &self.ctes)
+  }
+}
+
+/// reports(id, total) AS (SELECT ...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
+  pub name: Ident,
+  /// Names for the columns of the query, if they should be renamed
+  pub columns: Vec<Ident>,
+  pub query: SelectStatement,
+}
+
+impl fmt::Display for CommonTableExpression {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name)?;
+    if !self.columns.is_empty() {
+      write!(f, "(")?;
+      comma_separated(f, &self.columns)?;
+      write!(f, ")")?;
+    }
+    write!(f, " AS ({})", self.query)
+  }
+}
+
+/// A single SELECT, without anything that applies to the result of a
+/// compound select (i.e. ORDER BY and LIMIT)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectCore {
+  /// SELECT DISTINCT
+  pub distinct: bool,
+  pub columns: Vec<ResultColumn>,
+  /// SELECT * FROM users JOIN posts ON posts.user_id = users.id
+  pub from: Option<JoinClause>,
+  /// SELECT * FROM users WHERE <expr>
+  pub where_clause: Option<Expr>,
+  /// SELECT team, count(*) FROM users GROUP BY team
+  pub group_by: Vec<Expr>,
+  /// SELECT team FROM users GROUP BY team HAVING count(*) > 1
+  pub having: Option<Expr>,
+}
+
+impl fmt::Display for SelectCore {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let separator = if f.alternate() { "\n" } else { " " };
+    write!(f, "SELECT ")?;
+    if self.distinct {
+      write!(f, "DISTINCT ")?;
+    }
+    comma_separated(f, &self.columns)?;
+    if let Some(from) = &self.from {
+      write!(f, "{}FROM {}", separator, from)?;
+    }
+    if let Some(where_clause) = &self.where_clause {
+      write!(f, "{}WHERE {}", separator, where_clause)?;
+    }
+    if !self.group_by.is_empty() {
+      write!(f, "{}GROUP BY ", separator)?;
+      comma_separated(f, &self.group_by)?;
+    }
+    if let Some(having) = &self.having {
+      write!(f, "{}HAVING {}", separator, having)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundSelect {
+  pub operator: CompoundOperator,
+  pub core: SelectCore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompoundOperator {
+  /// UNION
+  Union,
+  /// UNION ALL
+  UnionAll,
+  /// INTERSECT
+  Intersect,
+  /// EXCEPT
+  Except,
+}
+
+impl fmt::Display for CompoundOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CompoundOperator::Union => write!(f, "UNION"),
+      CompoundOperator::UnionAll => write!(f, "UNION ALL"),
+      CompoundOperator::Intersect => write!(f, "INTERSECT"),
+      CompoundOperator::Except => write!(f, "EXCEPT"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+  pub limit: Expr,
+  pub offset: Option<Expr>,
+}
+
+impl fmt::Display for Limit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "LIMIT {}", self.limit)?;
+    if let Some(offset) = &self.offset {
+      write!(f, " OFFSET {}", offset)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+  pub expr: Expr,
+  pub order: SortOrder,
+}
+
+impl fmt::Display for OrderingTerm {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.order {
+      SortOrder::Asc => write!(f, "{}", self.expr),
+      SortOrder::Desc => write!(f, "{} DESC", self.expr),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+  Asc,
+  Desc,
+}
+
+/// A table, followed by any number of tables that are joined onto it
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinClause {
+  pub table: TableOrSubquery,
+  pub joins: Vec<Join>,
+}
+
+impl fmt::Display for JoinClause {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.table)?;
+    for join in self.joins.iter() {
+      write!(f, " {}", join)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+  pub operator: JoinOperator,
+  pub table: TableOrSubquery,
+  /// ON <expr>
+  pub constraint: Option<Expr>,
+}
+
+impl fmt::Display for Join {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {}", self.operator, self.table)?;
+    if let Some(constraint) = &self.constraint {
+      write!(f, " ON {}", constraint)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinOperator {
+  /// JOIN or INNER JOIN
+  Inner,
+  /// LEFT JOIN or LEFT OUTER JOIN
+  Left,
+  /// CROSS JOIN, or a comma between tables
+  Cross,
+}
+
+impl fmt::Display for JoinOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      JoinOperator::Inner => write!(f, "JOIN"),
+      JoinOperator::Left => write!(f, "LEFT JOIN"),
+      JoinOperator::Cross => write!(f, "CROSS JOIN"),
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct SelectStatement<'a> {
-  pub columns: Vec<ResultColumn<'a>>,
-  pub table: Option<Ident<'a>>,
+pub enum TableOrSubquery {
+  /// users [AS u]
+  Table { name: Ident, alias: Option<Ident> },
+  /// (SELECT * FROM users) [AS u]
+  Subquery {
+    query: Box<SelectStatement>,
+    alias: Option<Ident>,
+  },
+}
+
+impl fmt::Display for TableOrSubquery {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let alias = match self {
+      TableOrSubquery::Table { name, alias } => {
+        write!(f, "{}", name)?;
+        alias
+      }
+      TableOrSubquery::Subquery { query, alias } => {
+        write!(f, "({})", query)?;
+        alias
+      }
+    };
+    if let Some(alias) = alias {
+      write!(f, " AS {}", alias)?;
+    }
+    Ok(())
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum ResultColumn<'a> {
+pub enum ResultColumn {
   /// SELECT *
   Asterisk,
   /// SELECT table.*
-  TableAsterisk(Ident<'a>),
+  TableAsterisk(Ident),
 
   Expr {
-    value: Expr<'a>,
-    alias: Option<Ident<'a>>,
+    value: Expr,
+    alias: Option<Ident>,
   },
 }
 
+impl fmt::Display for ResultColumn {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ResultColumn::Asterisk => write!(f, "*"),
+      ResultColumn::TableAsterisk(table) => write!(f, "{}.*", table),
+      ResultColumn::Expr { value, alias: None } => write!(f, "{}", value),
+      ResultColumn::Expr {
+        value,
+        alias: Some(alias),
+      } => write!(f, "{} AS {}", value, alias),
+    }
+  }
+}
+
 /// Anywhere a column can appear, there can be:
 /// 1. just the column name
 /// 2. the column name + the table name
 #[derive(Debug, Clone, PartialEq)]
-pub struct ColumnIdent<'a> {
-  pub column: Ident<'a>,
-  pub table: Option<Ident<'a>>,
+pub struct ColumnIdent {
+  pub column: Ident,
+  pub table: Option<Ident>,
 }
 
-impl<'a> ToString for ColumnIdent<'a> {
-  fn to_string(&self) -> String {
+impl fmt::Display for ColumnIdent {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self.table {
-      None => format!("{}", self.column),
-      Some(ref table) => format!("{}.{}", table, self.column),
+      None => write!(f, "{}", self.column),
+      Some(ref table) => write!(f, "{}.{}", table, self.column),
     }
   }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr<'a> {
-  LiteralValue(LiteralValue<'a>),
-  ColumnIdent(ColumnIdent<'a>),
+pub enum Expr {
+  LiteralValue(LiteralValue),
+  ColumnIdent(ColumnIdent),
+  /// -price, NOT active
+  Unary {
+    op: UnaryOperator,
+    expr: Box<Expr>,
+  },
+  /// price * qty, a = 1 AND b <> 2
+  Binary {
+    op: BinaryOperator,
+    lhs: Box<Expr>,
+    rhs: Box<Expr>,
+  },
+  /// count(*), max(price)
+  FunctionCall(FunctionCall),
+  /// id IN (1, 2), id NOT IN (SELECT user_id FROM admins)
+  In {
+    expr: Box<Expr>,
+    not: bool,
+    list: InList,
+  },
+  /// EXISTS (SELECT * FROM posts WHERE posts.user_id = users.id)
+  Exists(Box<SelectStatement>),
+  /// (SELECT max(id) FROM users)
+  Subquery(Box<SelectStatement>),
+  /// ?, ?2, :name
+  Parameter(Parameter),
+  /// CASE [operand] WHEN a THEN b [ELSE c] END
+  Case {
+    /// With an operand, each WHEN is compared to it. Without one, each
+    /// WHEN is a condition.
+    operand: Option<Box<Expr>>,
+    when_then: Vec<(Expr, Expr)>,
+    else_expr: Option<Box<Expr>>,
+  },
+}
+
+/// How tightly each kind of expression binds, following the precedence
+/// levels of the grammar. Anything that binds less tightly than its
+/// position needs has to be wrapped in parentheses.
+const OR: u8 = 1;
+const AND: u8 = 2;
+const NOT: u8 = 3;
+const EQUALITY: u8 = 4;
+const IN: u8 = 5;
+const COMPARISON: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const UNARY: u8 = 9;
+const PRIMARY: u8 = 10;
+
+impl BinaryOperator {
+  fn precedence(self) -> u8 {
+    use self::BinaryOperator::*;
+    match self {
+      Multiply | Divide | Modulo => MULTIPLICATIVE,
+      Add | Subtract => ADDITIVE,
+      LessThan | LessThanOrEqual | GreaterThan | GreaterThanOrEqual => COMPARISON,
+      Equal | NotEqual | Is | IsNot => EQUALITY,
+      And => AND,
+      Or => OR,
+    }
+  }
+}
+
+impl Expr {
+  fn precedence(&self) -> u8 {
+    match self {
+      Expr::Unary {
+        op: UnaryOperator::Not,
+        ..
+      } => NOT,
+      Expr::Unary { .. } => UNARY,
+      Expr::Binary { op, .. } => op.precedence(),
+      Expr::In { .. } => IN,
+      // a negative number is a minus sign in front of a number, so it needs
+      // the same care as any other unary minus
+      Expr::LiteralValue(LiteralValue::NumericLiteral(value)) if *value < 0 => UNARY,
+      _ => PRIMARY,
+    }
+  }
+
+  fn is_number(&self) -> bool {
+    matches!(self, Expr::LiteralValue(LiteralValue::NumericLiteral(_)))
+  }
+
+  /// Writes the expression, in parentheses if it binds less tightly
+  /// than `precedence`
+  fn fmt_operand(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+    if self.precedence() < precedence {
+      write!(f, "({})", self)
+    } else {
+      write!(f, "{}", self)
+    }
+  }
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Expr::LiteralValue(value) => write!(f, "{}", value),
+      Expr::ColumnIdent(column_ident) => write!(f, "{}", column_ident),
+      Expr::Unary { op, expr } => {
+        write!(f, "{}", op)?;
+        match op {
+          // NOT NOT a reads fine, but -(-a) can't lose its parentheses
+          UnaryOperator::Not => expr.fmt_operand(f, NOT),
+          // -5 would come back as a negative number, not a minus sign in
+          // front of 5
+          UnaryOperator::Negate if expr.is_number() => write!(f, "({})", expr),
+          UnaryOperator::Negate | UnaryOperator::Plus => expr.fmt_operand(f, PRIMARY),
+        }
+      }
+      // operators are left associative, so the right hand side has to
+      // bind more tightly than the operator itself
+      Expr::Binary { op, lhs, rhs } => {
+        lhs.fmt_operand(f, op.precedence())?;
+        write!(f, " {} ", op)?;
+        rhs.fmt_operand(f, op.precedence() + 1)
+      }
+      Expr::FunctionCall(call) => write!(f, "{}", call),
+      Expr::In { expr, not, list } => {
+        expr.fmt_operand(f, IN)?;
+        if *not {
+          write!(f, " NOT")?;
+        }
+        write!(f, " IN (")?;
+        match list {
+          InList::Exprs(exprs) => comma_separated(f, exprs)?,
+          InList::Subquery(query) => write!(f, "{}", query)?,
+        }
+        write!(f, ")")
+      }
+      Expr::Exists(query) => write!(f, "EXISTS ({})", query),
+      Expr::Subquery(query) => write!(f, "({})", query),
+      Expr::Parameter(parameter) => write!(f, "{}", parameter.text),
+      Expr::Case {
+        operand,
+        when_then,
+        else_expr,
+      } => {
+        write!(f, "CASE")?;
+        if let Some(operand) = operand {
+          write!(f, " {}", operand)?;
+        }
+        for (when, then) in when_then.iter() {
+          write!(f, " WHEN {} THEN {}", when, then)?;
+        }
+        if let Some(else_expr) = else_expr {
+          write!(f, " ELSE {}", else_expr)?;
+        }
+        write!(f, " END")
+      }
+    }
+  }
+}
+
+/// A placeholder for a value that's bound when the query is run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+  /// The parameter as it was written, e.g. `?`, `?2` or `:name`
+  pub text: String,
+  /// Parameters are numbered from 1, the same way sqlite does it: `?NNN`
+  /// is number NNN, a `:name` that's been seen before gets the same number
+  /// as last time, and anything else gets the number after the biggest
+  /// one so far. This is 0 until the whole query has been parsed.
+  pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InList {
+  Exprs(Vec<Expr>),
+  Subquery(Box<SelectStatement>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCall {
+  pub name: Ident,
+  pub args: FunctionArgs,
+}
+
+impl fmt::Display for FunctionCall {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}(", self.name)?;
+    match &self.args {
+      FunctionArgs::Asterisk => write!(f, "*")?,
+      FunctionArgs::Exprs(args) => comma_separated(f, args)?,
+    }
+    write!(f, ")")
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionArgs {
+  /// count(*)
+  Asterisk,
+  Exprs(Vec<Expr>),
+}
+
+impl Expr {
+  pub fn unary(op: UnaryOperator, expr: Expr) -> Expr {
+    Expr::Unary {
+      op,
+      expr: Box::new(expr),
+    }
+  }
+  pub fn binary(op: BinaryOperator, lhs: Expr, rhs: Expr) -> Expr {
+    Expr::Binary {
+      op,
+      lhs: Box::new(lhs),
+      rhs: Box::new(rhs),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+  /// -
+  Negate,
+  /// +
+  Plus,
+  /// NOT
+  Not,
+}
+
+impl fmt::Display for UnaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      UnaryOperator::Negate => write!(f, "-"),
+      UnaryOperator::Plus => write!(f, "+"),
+      UnaryOperator::Not => write!(f, "NOT "),
+    }
+  }
+}
+
+/// Binary operators, listed from highest to lowest precedence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+  /// *
+  Multiply,
+  /// /
+  Divide,
+  /// %
+  Modulo,
+  /// +
+  Add,
+  /// -
+  Subtract,
+  /// <
+  LessThan,
+  /// <=
+  LessThanOrEqual,
+  /// >
+  GreaterThan,
+  /// >=
+  GreaterThanOrEqual,
+  /// = or ==
+  Equal,
+  /// != or <>
+  NotEqual,
+  /// IS
+  Is,
+  /// IS NOT
+  IsNot,
+  /// AND
+  And,
+  /// OR
+  Or,
+}
+
+impl fmt::Display for BinaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use self::BinaryOperator::*;
+    let text = match self {
+      Multiply => "*",
+      Divide => "/",
+      Modulo => "%",
+      Add => "+",
+      Subtract => "-",
+      LessThan => "<",
+      LessThanOrEqual => "<=",
+      GreaterThan => ">",
+      GreaterThanOrEqual => ">=",
+      Equal => "=",
+      NotEqual => "<>",
+      Is => "IS",
+      IsNot => "IS NOT",
+      And => "AND",
+      Or => "OR",
+    };
+    write!(f, "{}", text)
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum LiteralValue<'a> {
+pub enum LiteralValue {
   NumericLiteral(i64),
-  StringLiteral(&'a str),
-  BlobLiteral(&'a str),
+  StringLiteral(String),
+  BlobLiteral(String),
+  Null,
+}
+
+impl fmt::Display for LiteralValue {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LiteralValue::NumericLiteral(value) => write!(f, "{}", value),
+      LiteralValue::StringLiteral(value) => write!(f, "'{}'", value.replace('\'', "''")),
+      LiteralValue::BlobLiteral(value) => write!(f, "X'{}'", value.replace('\'', "''")),
+      LiteralValue::Null => write!(f, "NULL"),
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct InsertStatement<'a> {
-  pub table: Ident<'a>,
-  pub columns: Vec<Ident<'a>>,
+pub struct InsertStatement {
+  pub table: Ident,
+  pub columns: Vec<Ident>,
   /// VALUES (1, 'nlincoln'), (2, 'asdf')
-  pub values: InsertStatementValues<'a>,
+  pub values: InsertStatementValues,
 }
 
+impl fmt::Display for InsertStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "INSERT INTO {} (", self.table)?;
+    comma_separated(f, &self.columns)?;
+    write!(f, ") {}", self.values)
+  }
+}
+
+/// DELETE FROM users WHERE id = 1
 #[derive(Debug, Clone, PartialEq)]
-pub enum InsertStatementValues<'a> {
-  SingleRow(Vec<Expr<'a>>),
-  MultipleRows(Vec<Vec<Expr<'a>>>),
+pub struct DeleteStatement {
+  pub table: Ident,
+  pub where_clause: Option<Expr>,
+}
+
+impl fmt::Display for DeleteStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "DELETE FROM {}", self.table)?;
+    if let Some(where_clause) = &self.where_clause {
+      write!(f, " WHERE {}", where_clause)?;
+    }
+    Ok(())
+  }
+}
+
+/// UPDATE users SET active = 0, name = 'banned' WHERE id = 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateStatement {
+  pub table: Ident,
+  pub assignments: Vec<Assignment>,
+  pub where_clause: Option<Expr>,
+}
+
+impl fmt::Display for UpdateStatement {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "UPDATE {} SET ", self.table)?;
+    comma_separated(f, &self.assignments)?;
+    if let Some(where_clause) = &self.where_clause {
+      write!(f, " WHERE {}", where_clause)?;
+    }
+    Ok(())
+  }
+}
+
+/// name = 'banned'
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+  pub column: Ident,
+  pub value: Expr,
+}
+
+impl fmt::Display for Assignment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} = {}", self.column, self.value)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertStatementValues {
+  SingleRow(Vec<Expr>),
+  MultipleRows(Vec<Vec<Expr>>),
+}
+
+impl fmt::Display for InsertStatementValues {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let write_row = |f: &mut fmt::Formatter, row: &[Expr]| {
+      write!(f, "(")?;
+      comma_separated(f, row)?;
+      write!(f, ")")
+    };
+    match self {
+      InsertStatementValues::SingleRow(row) => {
+        write!(f, "VALUE ")?;
+        write_row(f, row)
+      }
+      InsertStatementValues::MultipleRows(rows) => {
+        write!(f, "VALUES ")?;
+        for (idx, row) in rows.iter().enumerate() {
+          if idx > 0 {
+            write!(f, ", ")?;
+          }
+          write_row(f, row)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::process_query;
+
+  fn assert_round_trip(query: &str, expected: &str) {
+    let statements = process_query(query).unwrap();
+    let printed = statements
+      .iter()
+      .map(|statement| format!("{};", statement))
+      .collect::<Vec<_>>()
+      .join("\n");
+    assert_eq!(printed, expected);
+    assert_eq!(process_query(&printed).unwrap(), statements);
+  }
+
+  #[test]
+  fn test_display_statements() {
+    assert_round_trip(
+      "create table users (id integer(8), name varchar(20));
+      create table t (a int, b smallint, c bigint, d blob(16), e char(2), f text);
+      alter table users add age integer;
+      alter table users rename age to years;
+      insert into users (id, name) value (1, 'it''s a');
+      insert into users (id, name) values (2, 'b'), (?, :name);
+      update users set name = 'c', id = id + 1 where id = 1;
+      delete from users where id in (1, 2);
+      drop table if exists users;",
+      "CREATE TABLE users (id INTEGER(8), name VARCHAR(20));
+CREATE TABLE t (a INT, b SMALLINT, c BIGINT, d BLOB(16), e CHAR(2), f TEXT);
+ALTER TABLE users ADD COLUMN age INTEGER;
+ALTER TABLE users RENAME COLUMN age TO years;
+INSERT INTO users (id, name) VALUE (1, 'it''s a');
+INSERT INTO users (id, name) VALUES (2, 'b'), (?, :name);
+UPDATE users SET name = 'c', id = id + 1 WHERE id = 1;
+DELETE FROM users WHERE id IN (1, 2);
+DROP TABLE IF EXISTS users;",
+    );
+    assert_round_trip(
+      "with recursive n(i) as (select 1 union all select i + 1 from n where i < 3)
+      select distinct u.*, count(*) c from users as u, teams
+        left outer join posts p on p.user_id = u.id
+        where exists (select 1) and u.id not in (select id from admins)
+        group by u.team having count(*) > 1
+      except select * from (select * from users) u
+      order by 1, 2 desc limit 10 offset 5;",
+      "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3) \
+SELECT DISTINCT u.*, count(*) AS c FROM users AS u CROSS JOIN teams \
+LEFT JOIN posts AS p ON p.user_id = u.id \
+WHERE EXISTS (SELECT 1) AND u.id NOT IN (SELECT id FROM admins) \
+GROUP BY u.team HAVING count(*) > 1 \
+EXCEPT SELECT * FROM (SELECT * FROM users) AS u \
+ORDER BY 1, 2 DESC LIMIT 10 OFFSET 5;",
+    );
+  }
+
+  #[test]
+  fn test_display_expr_parentheses() {
+    assert_round_trip(
+      "select (1 + 2) * 3, 1 - (2 - 3), (1 - 2) - 3, - -a, -(a * b), not not a,
+        (a = 1) = (b or c), not a = 1, (not a) = 1, (a in (1)) in (b), a is not null,
+        case when a then x'ab' else (select max(b) from t) end, case a when 1 then 2 end
+        from t;",
+      "SELECT (1 + 2) * 3, 1 - (2 - 3), 1 - 2 - 3, -(-a), -(a * b), NOT NOT a, \
+a = 1 = (b OR c), NOT a = 1, (NOT a) = 1, a IN (1) IN (b), a IS NOT NULL, \
+CASE WHEN a THEN X'ab' ELSE (SELECT max(b) FROM t) END, CASE a WHEN 1 THEN 2 END \
+FROM t;",
+    );
+  }
+
+  #[test]
+  fn test_display_numbers() {
+    assert_round_trip(
+      "select -5, - -5, -(5), 0x10, 2 - -3, 2 * -(3), -9223372036854775808;",
+      "SELECT -5, -(-5), -(5), 16, 2 - -3, 2 * -(3), -9223372036854775808;",
+    );
+  }
+
+  #[test]
+  fn test_display_literals() {
+    assert_round_trip(
+      "select 'it''s', x'a''b', null;",
+      "SELECT 'it''s', X'a''b', NULL;",
+    );
+  }
+
+  #[test]
+  fn test_display_idents() {
+    use super::Ident;
+    assert_eq!(Ident::new("users").to_string(), "users");
+    assert_eq!(Ident::new("Größe").to_string(), "größe");
+    assert_eq!(Ident::quoted("Größe").to_string(), r#""Größe""#);
+    assert_eq!(Ident::new("select").to_string(), r#""select""#);
+    assert_eq!(Ident::new("user name").to_string(), r#""user name""#);
+    assert_eq!(Ident::quoted("users").to_string(), r#""users""#);
+    assert_eq!(Ident::quoted(r#"say "hi""#).to_string(), r#""say ""hi""""#);
+    assert_round_trip(
+      r#"select `x`, "t".名前 from "table" as "a `b`" where `value` = 1;"#,
+      r#"SELECT "x", "t".名前 FROM "table" AS "a `b`" WHERE "value" = 1;"#,
+    );
+    assert_round_trip(
+      "select x, x.x, x'ab' from t as x;",
+      "SELECT x, x.x, X'ab' FROM t AS x;",
+    );
+  }
+
+  #[test]
+  fn test_display_alternate() {
+    let statements = process_query(
+      "create table users (id integer(8), name varchar(20));
+      select id from users where id > 1 union select 1 order by 1;",
+    )
+    .unwrap();
+    assert_eq!(
+      format!("{:#}", statements[0]),
+      "CREATE TABLE users (\n  id INTEGER(8),\n  name VARCHAR(20)\n)"
+    );
+    assert_eq!(
+      format!("{:#}", statements[1]),
+      "SELECT id\nFROM users\nWHERE id > 1\nUNION\nSELECT 1\nORDER BY 1"
+    );
+  }
 }
@@ -0,0 +1,251 @@
+use crate::tokenizer::{Pos, Token};
+use crate::Kind;
+use combine::easy::{self, Info};
+use std::fmt;
+
+/// A query that doesn't match the grammar. Displaying it shows the line
+/// the error is on, with a caret under the part that couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+  /// Where the error starts
+  pub position: Pos,
+  /// What was found at `position`
+  pub unexpected: Unexpected,
+  /// The kinds of token that could have been there instead
+  pub expected: Vec<Kind>,
+  /// The line of the query that the error is on
+  line: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unexpected {
+  /// A token that doesn't fit the grammar
+  Token {
+    kind: Kind,
+    text: String,
+  },
+  /// Something that isn't a token at all
+  Message(String),
+  EndOfInput,
+}
+
+impl ParseError {
+  pub(crate) fn new<'a>(
+    source: &str,
+    errors: easy::Errors<Token<'a, Kind>, Token<'a, Kind>, Pos>,
+  ) -> ParseError {
+    let mut unexpected = None;
+    let mut expected = vec![];
+    for error in errors.errors {
+      let found = match error {
+        easy::Error::Expected(Info::Token(token)) => {
+          if !expected.contains(&token.kind) {
+            expected.push(token.kind);
+          }
+          continue;
+        }
+        // the only other thing that gets expected is the end of the input
+        easy::Error::Expected(_) => continue,
+        easy::Error::Unexpected(Info::Token(token))
+        | easy::Error::Unexpected(Info::Range(token)) => Unexpected::Token {
+          kind: token.kind,
+          text: token.value.to_string(),
+        },
+        easy::Error::Unexpected(Info::Borrowed("end of input")) => Unexpected::EndOfInput,
+        easy::Error::Unexpected(info) | easy::Error::Message(info) => {
+          Unexpected::Message(match info {
+            Info::Owned(message) => message,
+            Info::Borrowed(message) => message.to_string(),
+            Info::Token(token) | Info::Range(token) => token.value.to_string(),
+          })
+        }
+        easy::Error::Other(error) => Unexpected::Message(error.to_string()),
+      };
+      // the first thing that was unexpected is what actually stopped the parse
+      unexpected = unexpected.or(Some(found));
+    }
+    let line = source.lines().nth(errors.position.line - 1).unwrap_or("");
+    ParseError {
+      position: errors.position,
+      unexpected: unexpected.unwrap_or(Unexpected::EndOfInput),
+      expected,
+      line: line.to_string(),
+    }
+  }
+
+  /// The line of the query that the error is on
+  pub fn line(&self) -> &str {
+    &self.line
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Parse error at {}: ", self.position)?;
+    match &self.unexpected {
+      Unexpected::Token { text, .. } => write!(f, "unexpected {:?}", text)?,
+      Unexpected::Message(message) => write!(f, "{}", message)?,
+      Unexpected::EndOfInput => write!(f, "unexpected end of input")?,
+    }
+    for (idx, kind) in self.expected.iter().enumerate() {
+      let separator = match idx {
+        0 => ", expected ",
+        _ if idx + 1 == self.expected.len() => " or ",
+        _ => ", ",
+      };
+      write!(f, "{}{}", separator, kind)?;
+    }
+
+    let line = self.line();
+    let number = self.position.line.to_string();
+    let gutter = " ".repeat(number.len());
+    // the tokenizer counts a tab between tokens as two columns. The tabs are
+    // copied over, so that the caret lines up however wide they're shown
+    let mut indent = String::new();
+    let mut column = 1;
+    for c in line.chars() {
+      if column >= self.position.column {
+        break;
+      }
+      if c == '\t' {
+        indent.push('\t');
+        column += 2;
+      } else {
+        indent.push(' ');
+        column += 1;
+      }
+    }
+    let width = match &self.unexpected {
+      // a string can run over several lines, but only the first one is shown
+      Unexpected::Token { text, .. } => text.lines().next().unwrap_or("").chars().count().max(1),
+      _ => 1,
+    };
+    writeln!(f)?;
+    writeln!(f, "{} |", gutter)?;
+    writeln!(f, "{} | {}", number, line)?;
+    write!(f, "{} | {}{}", gutter, indent, "^".repeat(width))
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::process_query;
+
+  fn parse_error(query: &str) -> ParseError {
+    match process_query(query) {
+      Err(crate::AstError::ParseError(err)) => err,
+      Ok(_) => panic!("{} should not parse", query),
+    }
+  }
+
+  #[test]
+  fn test_parse_error() {
+    let err = parse_error("select id from users;\nselect * frm users;");
+    assert_eq!(
+      err.position,
+      Pos {
+        line: 2,
+        column: 10
+      }
+    );
+    assert_eq!(
+      err.unexpected,
+      Unexpected::Token {
+        kind: Kind::Ident,
+        text: "frm".to_string()
+      }
+    );
+    assert_eq!(err.expected, vec![Kind::SemiColon]);
+    assert_eq!(
+      err.to_string(),
+      [
+        "Parse error at 2:10: unexpected \"frm\", expected \";\"",
+        "  |",
+        "2 | select * frm users;",
+        "  |          ^^^",
+      ]
+      .join("\n")
+    );
+
+    let err = parse_error("select id\nfrom users where id = ;");
+    assert_eq!(
+      err.position,
+      Pos {
+        line: 2,
+        column: 23
+      }
+    );
+    assert!(err.expected.contains(&Kind::NumericLiteral));
+    assert!(err.expected.contains(&Kind::LeftParen));
+
+    let err = parse_error("select $ from users;");
+    assert_eq!(
+      err.unexpected,
+      Unexpected::Message("unexpected character '$'".to_string())
+    );
+
+    let err = parse_error("select 'it''s\nü', € from users;");
+    assert_eq!(err.position, Pos { line: 2, column: 5 });
+    assert_eq!(err.to_string().lines().last(), Some("  |     ^"));
+
+    let err = parse_error("select 1, 99999999999999999999;");
+    assert_eq!(
+      err.to_string(),
+      [
+        "Parse error at 1:11: 99999999999999999999 is out of range",
+        "  |",
+        "1 | select 1, 99999999999999999999;",
+        "  |           ^",
+      ]
+      .join("\n")
+    );
+    let err = parse_error("select -0x8000000000000000;");
+    assert_eq!(
+      err.unexpected,
+      Unexpected::Message("-0x8000000000000000 is out of range".to_string())
+    );
+    let err = parse_error("create table t (a integer(1.5));");
+    assert_eq!(
+      err.unexpected,
+      Unexpected::Message("1.5 is a real number, and only integers are supported".to_string())
+    );
+    for (query, number) in &[("select 1.5;", "1.5"), ("select 2 * -.5e3;", "-.5e3")] {
+      assert_eq!(
+        parse_error(query).unexpected,
+        Unexpected::Message(format!(
+          "{} is a real number, and only integers are supported",
+          number
+        ))
+      );
+    }
+
+    let err = parse_error("/* users\n */ select * -- everything\nfrm users;");
+    assert_eq!(err.position, Pos { line: 3, column: 1 });
+
+    let err = parse_error("select\t\tfoo bar baz;");
+    assert_eq!(
+      err.position,
+      Pos {
+        line: 1,
+        column: 19
+      }
+    );
+    assert_eq!(
+      err.to_string().lines().last(),
+      Some("  |       \t\t        ^^^")
+    );
+
+    let err = parse_error("select id from users");
+    assert_eq!(err.unexpected, Unexpected::EndOfInput);
+    assert_eq!(
+      err.position,
+      Pos {
+        line: 1,
+        column: 21
+      }
+    );
+  }
+}
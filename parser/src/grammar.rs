@@ -1,30 +1,32 @@
 use crate::ast::*;
 use crate::tokenizer::{self, Pos, Token};
-use crate::{Kind, Sql};
+use crate::{Kind, ParseError, Sql};
 
 use combine::stream::easy::{Error, Errors, Info};
-use combine::StreamOnce;
 use combine::{satisfy, ConsumedResult, Parser};
 use std::marker::PhantomData;
 
-use combine::error::{Consumed, Tracked};
+use combine::error::{FastResult, Tracked};
 
-pub type ParseError<'a> = Consumed<Tracked<<TokenStream<'a> as StreamOnce>::Error>>;
-
-pub fn parse<'a>(input: &'a str) -> Result<Vec<Statement<'a>>, ParseError<'a>> {
-  use combine::parser::repeat::many1;
-  many1(statement())
+pub fn parse(input: &str) -> Result<Vec<Statement>, ParseError> {
+  use combine::parser::{item::eof, repeat::many1};
+  (many1(statement()), eof())
     .parse_stream(&mut TokenStream::new(Sql(()), input))
-    .map(|result| result.0)
+    .map(|((statements, _), _)| statements)
+    .map_err(|err| ParseError::new(input, err.into_inner().error))
 }
 
-fn statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Statement<'a>> {
+fn statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Statement> {
   use combine::parser::choice::choice;
   (
     choice((
       create_table_statement().map(Statement::CreateTable),
       select_statement().map(Statement::Select),
       insert_statement().map(Statement::Insert),
+      delete_statement().map(Statement::Delete),
+      update_statement().map(Statement::Update),
+      drop_table_statement().map(Statement::DropTable),
+      alter_table_statement().map(Statement::AlterTable),
     )),
     token(Kind::SemiColon),
   )
@@ -32,7 +34,7 @@ fn statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Statement<'a
 }
 
 fn create_table_statement<'a>(
-) -> impl Parser<Input = TokenStream<'a>, Output = CreateTableStatement<'a>> {
+) -> impl Parser<Input = TokenStream<'a>, Output = CreateTableStatement> {
   use combine::parser::repeat::sep_by1;
   (
     token(Kind::Create),
@@ -50,7 +52,123 @@ fn create_table_statement<'a>(
     )
 }
 
-fn column_def<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ColumnDef<'a>> {
+fn drop_table_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = DropTableStatement> {
+  use combine::parser::choice::optional;
+  (
+    (token(Kind::Drop), token(Kind::Table)),
+    optional((token(Kind::If), token(Kind::Exists))),
+    ident(),
+  )
+    .map(|(_, if_exists, table_name)| DropTableStatement {
+      table_name,
+      if_exists: if_exists.is_some(),
+    })
+}
+
+#[test]
+fn test_drop_table_statement() {
+  assert_ast(
+    drop_table_statement(),
+    "DROP TABLE users",
+    DropTableStatement {
+      table_name: Ident::new("users"),
+      if_exists: false,
+    },
+  );
+  assert_ast(
+    drop_table_statement(),
+    "drop table if exists users",
+    DropTableStatement {
+      table_name: Ident::new("users"),
+      if_exists: true,
+    },
+  );
+}
+
+fn alter_table_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = AlterTableStatement>
+{
+  use combine::parser::choice::{choice, optional};
+  let add_column = (
+    token(Kind::Add),
+    optional(token(Kind::Column)),
+    column_def(),
+  )
+    .map(|(_, _, column_def)| AlterTableAction::AddColumn(column_def));
+  let rename = (
+    token(Kind::Rename),
+    choice((
+      (token(Kind::To), ident()).map(|(_, to)| AlterTableAction::RenameTable(to)),
+      (
+        optional(token(Kind::Column)),
+        ident(),
+        token(Kind::To),
+        ident(),
+      )
+        .map(|(_, from, _, to)| AlterTableAction::RenameColumn { from, to }),
+    )),
+  )
+    .map(|(_, action)| action);
+
+  (
+    (token(Kind::Alter), token(Kind::Table)),
+    ident(),
+    choice((add_column, rename)),
+  )
+    .map(|(_, table_name, action)| AlterTableStatement { table_name, action })
+}
+
+#[test]
+fn test_alter_table_statement() {
+  let alter_users = |action| AlterTableStatement {
+    table_name: Ident::new("users"),
+    action,
+  };
+  assert_ast(
+    alter_table_statement(),
+    "ALTER TABLE users ADD COLUMN age INTEGER",
+    alter_users(AlterTableAction::AddColumn(ColumnDef {
+      column_name: Ident::new("age"),
+      type_name: TypeName {
+        name: Type::Integer,
+        argument: None,
+      },
+    })),
+  );
+  assert_ast(
+    alter_table_statement(),
+    "alter table users add age integer(4)",
+    alter_users(AlterTableAction::AddColumn(ColumnDef {
+      column_name: Ident::new("age"),
+      type_name: TypeName {
+        name: Type::Integer,
+        argument: Some(4),
+      },
+    })),
+  );
+  assert_ast(
+    alter_table_statement(),
+    "alter table users rename column age to years",
+    alter_users(AlterTableAction::RenameColumn {
+      from: Ident::new("age"),
+      to: Ident::new("years"),
+    }),
+  );
+  assert_ast(
+    alter_table_statement(),
+    "alter table users rename age to years",
+    alter_users(AlterTableAction::RenameColumn {
+      from: Ident::new("age"),
+      to: Ident::new("years"),
+    }),
+  );
+  assert_ast(
+    alter_table_statement(),
+    "alter table users rename to people",
+    alter_users(AlterTableAction::RenameTable(Ident::new("people"))),
+  );
+}
+
+fn column_def<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ColumnDef> {
   (ident(), type_name()).map(|(column_name, type_name)| ColumnDef {
     column_name,
     type_name,
@@ -65,7 +183,7 @@ fn type_name<'a>() -> impl Parser<Input = TokenStream<'a>, Output = TypeName> {
     optional(
       (
         token(Kind::LeftParen),
-        numeric_literal(),
+        integer_literal(),
         token(Kind::RightParen),
       )
         .map(|(_, num, _)| num),
@@ -77,24 +195,359 @@ fn type_name<'a>() -> impl Parser<Input = TokenStream<'a>, Output = TypeName> {
 fn r#type<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Type> {
   use combine::parser::choice::choice;
 
+  // the rest of the type names aren't keywords, so that they can still
+  // be used to name columns and tables
   choice((
     token(Kind::Integer).map(|_| Type::Integer),
     token(Kind::Varchar).map(|_| Type::Varchar),
+    token(Kind::Ident).and_then(|token| {
+      let name = match token.value.to_lowercase().as_str() {
+        "int" => Type::Int,
+        "smallint" => Type::SmallInt,
+        "bigint" => Type::BigInt,
+        "blob" => Type::Blob,
+        "char" => Type::Char,
+        "text" => Type::Text,
+        _ => {
+          return Err(Error::Message(Info::Owned(format!(
+            "{} is not a type",
+            token.value
+          ))))
+        }
+      };
+      Ok(name)
+    }),
   ))
 }
 
-fn select_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = SelectStatement<'a>> {
-  use combine::parser::{choice::optional, repeat::sep_by1};
+// Subqueries make select statements recursive, so just like `expr` this
+// has to be a named parser type
+parser! {
+  fn select_statement['a]()(TokenStream<'a>) -> SelectStatement {
+    use combine::parser::{
+      choice::{choice, optional},
+      repeat::{many, sep_by1},
+    };
+
+    let compound_operator = choice((
+      (token(Kind::Union), optional(token(Kind::All))).map(|(_, all)| match all {
+        Some(_) => CompoundOperator::UnionAll,
+        None => CompoundOperator::Union,
+      }),
+      token(Kind::Intersect).map(|_| CompoundOperator::Intersect),
+      token(Kind::Except).map(|_| CompoundOperator::Except),
+    ));
+
+    (
+      optional(with_clause()),
+      select_core(),
+      many(
+        (compound_operator, select_core())
+          .map(|(operator, core)| CompoundSelect { operator, core }),
+      ),
+      optional(
+        (
+          (token(Kind::Order), token(Kind::By)),
+          sep_by1(ordering_term(), token(Kind::Comma)),
+        )
+          .map(|(_, terms)| terms),
+      ),
+      optional(limit()),
+    )
+      .map(|(with, core, compound, order_by, limit)| SelectStatement {
+        with,
+        core,
+        compound,
+        order_by: order_by.unwrap_or_default(),
+        limit,
+      })
+  }
+}
+
+fn with_clause<'a>() -> impl Parser<Input = TokenStream<'a>, Output = WithClause> {
+  use combine::parser::{
+    choice::optional,
+    repeat::{sep_by, sep_by1},
+  };
+  let column_names = optional(
+    (
+      token(Kind::LeftParen),
+      sep_by(ident(), token(Kind::Comma)),
+      token(Kind::RightParen),
+    )
+      .map(|(_, columns, _)| columns),
+  );
+  let cte = (
+    ident(),
+    column_names,
+    token(Kind::As),
+    token(Kind::LeftParen),
+    select_statement(),
+    token(Kind::RightParen),
+  )
+    .map(|(name, columns, _, _, query, _)| CommonTableExpression {
+      name,
+      columns: columns.unwrap_or_default(),
+      query,
+    });
+  (
+    token(Kind::With),
+    optional(token(Kind::Recursive)),
+    sep_by1(cte, token(Kind::Comma)),
+  )
+    .map(|(_, recursive, ctes)| WithClause {
+      recursive: recursive.is_some(),
+      ctes,
+    })
+}
+
+#[test]
+fn test_with_clause() {
+  let select_one = || SelectStatement {
+    with: None,
+    core: SelectCore {
+      distinct: false,
+      columns: vec![ResultColumn::Expr {
+        value: Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+        alias: None,
+      }],
+      from: None,
+      where_clause: None,
+      group_by: vec![],
+      having: None,
+    },
+    compound: vec![],
+    order_by: vec![],
+    limit: None,
+  };
+  assert_ast(
+    with_clause(),
+    "WITH RECURSIVE a(n) AS (SELECT 1), b AS (SELECT 1)",
+    WithClause {
+      recursive: true,
+      ctes: vec![
+        CommonTableExpression {
+          name: Ident::new("a"),
+          columns: vec![Ident::new("n")],
+          query: select_one(),
+        },
+        CommonTableExpression {
+          name: Ident::new("b"),
+          columns: vec![],
+          query: select_one(),
+        },
+      ],
+    },
+  );
+}
+
+fn select_core<'a>() -> impl Parser<Input = TokenStream<'a>, Output = SelectCore> {
+  use combine::parser::{
+    choice::{choice, optional},
+    repeat::sep_by1,
+  };
 
   (
     token(Kind::Select),
+    optional(choice((
+      token(Kind::Distinct).map(|_| true),
+      token(Kind::All).map(|_| false),
+    ))),
     sep_by1(result_column(), token(Kind::Comma)),
-    optional((token(Kind::From), ident()).map(|(_, tables)| tables)),
+    optional((token(Kind::From), join_clause()).map(|(_, from)| from)),
+    optional((token(Kind::Where), expr()).map(|(_, expr)| expr)),
+    optional(
+      (
+        (token(Kind::Group), token(Kind::By)),
+        sep_by1(expr(), token(Kind::Comma)),
+      )
+        .map(|(_, group_by)| group_by),
+    ),
+    optional((token(Kind::Having), expr()).map(|(_, expr)| expr)),
+  )
+    .map(
+      |(_, distinct, columns, from, where_clause, group_by, having)| SelectCore {
+        distinct: distinct.unwrap_or(false),
+        columns,
+        from,
+        where_clause,
+        group_by: group_by.unwrap_or_default(),
+        having,
+      },
+    )
+}
+
+fn limit<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Limit> {
+  use combine::parser::choice::optional;
+  (
+    token(Kind::Limit),
+    expr(),
+    optional((token(Kind::Offset), expr()).map(|(_, offset)| offset)),
   )
-    .map(|(_, columns, table)| SelectStatement { columns, table })
+    .map(|(_, limit, offset)| Limit { limit, offset })
 }
 
-fn result_column<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ResultColumn<'a>> {
+#[test]
+fn test_limit() {
+  let number = |n| Expr::LiteralValue(LiteralValue::NumericLiteral(n));
+  assert_ast(
+    limit(),
+    "LIMIT 10",
+    Limit {
+      limit: number(10),
+      offset: None,
+    },
+  );
+  assert_ast(
+    limit(),
+    "limit 10 offset 2 * 10",
+    Limit {
+      limit: number(10),
+      offset: Some(Expr::binary(
+        BinaryOperator::Multiply,
+        number(2),
+        number(10),
+      )),
+    },
+  );
+}
+
+fn ordering_term<'a>() -> impl Parser<Input = TokenStream<'a>, Output = OrderingTerm> {
+  use combine::parser::choice::{choice, optional};
+  (
+    expr(),
+    optional(choice((
+      token(Kind::Asc).map(|_| SortOrder::Asc),
+      token(Kind::Desc).map(|_| SortOrder::Desc),
+    ))),
+  )
+    .map(|(expr, order)| OrderingTerm {
+      expr,
+      order: order.unwrap_or(SortOrder::Asc),
+    })
+}
+
+#[test]
+fn test_ordering_term() {
+  let column = |name| {
+    Expr::ColumnIdent(ColumnIdent {
+      column: Ident::new(name),
+      table: None,
+    })
+  };
+  assert_ast(
+    ordering_term(),
+    "name",
+    OrderingTerm {
+      expr: column("name"),
+      order: SortOrder::Asc,
+    },
+  );
+  assert_ast(
+    ordering_term(),
+    "-id DESC",
+    OrderingTerm {
+      expr: Expr::unary(UnaryOperator::Negate, column("id")),
+      order: SortOrder::Desc,
+    },
+  );
+}
+
+fn join_clause<'a>() -> impl Parser<Input = TokenStream<'a>, Output = JoinClause> {
+  use combine::parser::repeat::many;
+  (table_or_subquery(), many(join())).map(|(table, joins)| JoinClause { table, joins })
+}
+
+fn join<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Join> {
+  use combine::parser::choice::{choice, optional};
+  let join_operator = choice((
+    token(Kind::Inner).map(|_| JoinOperator::Inner),
+    (token(Kind::Left), optional(token(Kind::Outer))).map(|_| JoinOperator::Left),
+    token(Kind::Cross).map(|_| JoinOperator::Cross),
+  ));
+  let operator = choice((
+    token(Kind::Comma).map(|_| JoinOperator::Cross),
+    (optional(join_operator), token(Kind::Join))
+      .map(|(operator, _)| operator.unwrap_or(JoinOperator::Inner)),
+  ));
+  (
+    operator,
+    table_or_subquery(),
+    optional((token(Kind::On), expr()).map(|(_, expr)| expr)),
+  )
+    .map(|(operator, table, constraint)| Join {
+      operator,
+      table,
+      constraint,
+    })
+}
+
+fn table_or_subquery<'a>() -> impl Parser<Input = TokenStream<'a>, Output = TableOrSubquery> {
+  use combine::parser::choice::{choice, optional};
+  let alias = || optional((optional(token(Kind::As)), ident()).map(|(_, alias)| alias));
+  choice((
+    (ident(), alias()).map(|(name, alias)| TableOrSubquery::Table { name, alias }),
+    (
+      token(Kind::LeftParen),
+      select_statement(),
+      token(Kind::RightParen),
+      alias(),
+    )
+      .map(|(_, query, _, alias)| TableOrSubquery::Subquery {
+        query: Box::new(query),
+        alias,
+      }),
+  ))
+}
+
+#[test]
+fn test_join_clause() {
+  let table = |name, alias: Option<&'static str>| TableOrSubquery::Table {
+    name: Ident::new(name),
+    alias: alias.map(Ident::new),
+  };
+  let column = |table, column| {
+    Expr::ColumnIdent(ColumnIdent {
+      column: Ident::new(column),
+      table: Some(Ident::new(table)),
+    })
+  };
+  assert_ast(
+    join_clause(),
+    "users u, teams JOIN posts AS p ON p.user_id = u.id LEFT OUTER JOIN likes ON 1 CROSS JOIN tags",
+    JoinClause {
+      table: table("users", Some("u")),
+      joins: vec![
+        Join {
+          operator: JoinOperator::Cross,
+          table: table("teams", None),
+          constraint: None,
+        },
+        Join {
+          operator: JoinOperator::Inner,
+          table: table("posts", Some("p")),
+          constraint: Some(Expr::binary(
+            BinaryOperator::Equal,
+            column("p", "user_id"),
+            column("u", "id"),
+          )),
+        },
+        Join {
+          operator: JoinOperator::Left,
+          table: table("likes", None),
+          constraint: Some(Expr::LiteralValue(LiteralValue::NumericLiteral(1))),
+        },
+        Join {
+          operator: JoinOperator::Cross,
+          table: table("tags", None),
+          constraint: None,
+        },
+      ],
+    },
+  );
+}
+
+fn result_column<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ResultColumn> {
   use combine::parser::{
     choice::{choice, optional},
     combinator::attempt,
@@ -114,7 +567,7 @@ fn result_column<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ResultCo
   ))
 }
 
-fn column_ident<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ColumnIdent<'a>> {
+fn column_ident<'a>() -> impl Parser<Input = TokenStream<'a>, Output = ColumnIdent> {
   use combine::parser::{choice::choice, combinator::attempt};
   choice((
     attempt(
@@ -150,47 +603,564 @@ fn test_column_ident() {
   );
 }
 
-fn expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr<'a>> {
+// Expressions are recursive (parentheses can hold a whole new expression),
+// so the entry point has to be a named parser type instead of an `impl Parser`
+parser! {
+  fn expr['a]()(TokenStream<'a>) -> Expr {
+    or_expr()
+  }
+}
+
+/*
+ * Each precedence level of the expression grammar gets its own parser,
+ * from lowest to highest precedence (this matches sqlite):
+ *
+ * OR
+ * AND
+ * NOT
+ * = == != <> IS IS NOT
+ * IN NOT IN
+ * < <= > >=
+ * + -
+ * * / %
+ * unary - +
+ */
+
+/// A left-associative chain of `operand`s, separated by `operator`s
+fn binary_level<'a>(
+  operand: impl Parser<Input = TokenStream<'a>, Output = Expr>,
+  operator: impl Parser<Input = TokenStream<'a>, Output = BinaryOperator>,
+) -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::repeat::chainl1;
+  chainl1(
+    operand,
+    operator.map(|op| move |lhs: Expr, rhs: Expr| Expr::binary(op, lhs, rhs)),
+  )
+}
+
+fn or_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  binary_level(and_expr(), token(Kind::Or).map(|_| BinaryOperator::Or))
+}
+
+fn and_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  binary_level(not_expr(), token(Kind::And).map(|_| BinaryOperator::And))
+}
+
+fn not_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::repeat::many;
+  (many::<Vec<_>, _>(token(Kind::Not)), equality_expr()).map(|(nots, expr)| {
+    nots
+      .into_iter()
+      .fold(expr, |expr, _| Expr::unary(UnaryOperator::Not, expr))
+  })
+}
+
+fn equality_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::choice::{choice, optional};
+  binary_level(
+    in_expr(),
+    choice((
+      token(Kind::Equal).map(|_| BinaryOperator::Equal),
+      token(Kind::DoubleEqual).map(|_| BinaryOperator::Equal),
+      token(Kind::NotEqual).map(|_| BinaryOperator::NotEqual),
+      token(Kind::LessThanGreaterThan).map(|_| BinaryOperator::NotEqual),
+      (token(Kind::Is), optional(token(Kind::Not))).map(|(_, not)| match not {
+        Some(_) => BinaryOperator::IsNot,
+        None => BinaryOperator::Is,
+      }),
+    )),
+  )
+}
+
+fn in_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::{
+    choice::{choice, optional},
+    repeat::{many, sep_by},
+  };
+  let list = choice((
+    select_statement().map(|query| InList::Subquery(Box::new(query))),
+    sep_by(expr(), token(Kind::Comma)).map(InList::Exprs),
+  ));
+  let suffix = (
+    optional(token(Kind::Not)),
+    token(Kind::In),
+    token(Kind::LeftParen),
+    list,
+    token(Kind::RightParen),
+  )
+    .map(|(not, _, _, list, _)| (not.is_some(), list));
+  (comparison_expr(), many::<Vec<_>, _>(suffix)).map(|(expr, suffixes)| {
+    suffixes
+      .into_iter()
+      .fold(expr, |expr, (not, list)| Expr::In {
+        expr: Box::new(expr),
+        not,
+        list,
+      })
+  })
+}
+
+fn comparison_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::choice::choice;
+  binary_level(
+    additive_expr(),
+    choice((
+      token(Kind::LessThan).map(|_| BinaryOperator::LessThan),
+      token(Kind::LessThanOrEqual).map(|_| BinaryOperator::LessThanOrEqual),
+      token(Kind::GreaterThan).map(|_| BinaryOperator::GreaterThan),
+      token(Kind::GreaterThanOrEqual).map(|_| BinaryOperator::GreaterThanOrEqual),
+    )),
+  )
+}
+
+fn additive_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::choice::choice;
+  binary_level(
+    multiplicative_expr(),
+    choice((
+      token(Kind::Plus).map(|_| BinaryOperator::Add),
+      token(Kind::Minus).map(|_| BinaryOperator::Subtract),
+    )),
+  )
+}
+
+fn multiplicative_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::choice::choice;
+  binary_level(
+    unary_expr(),
+    choice((
+      token(Kind::Asterisk).map(|_| BinaryOperator::Multiply),
+      token(Kind::Slash).map(|_| BinaryOperator::Divide),
+      token(Kind::Percent).map(|_| BinaryOperator::Modulo),
+    )),
+  )
+}
+
+/// What a unary operator applies to. Numbers are kept as text until we
+/// know whether they're negative, since -9223372036854775808 fits in an
+/// i64 but 9223372036854775808 doesn't.
+enum Operand<'a> {
+  Number(&'a str),
+  Expr(Expr),
+}
+
+fn unary_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::{choice::choice, repeat::many};
+  let operator = choice((
+    token(Kind::Minus).map(|_| UnaryOperator::Negate),
+    token(Kind::Plus).map(|_| UnaryOperator::Plus),
+  ));
+  let operand = choice((
+    token(Kind::NumericLiteral).map(|token| Operand::Number(token.value)),
+    primary_expr().map(Operand::Expr),
+  ));
+  (many::<Vec<_>, _>(operator), operand).and_then(
+    |(mut operators, operand)| -> Result<_, NumberError<'a>> {
+      let expr = match operand {
+        Operand::Number(text) => {
+          // a minus right in front of a number makes it a negative number
+          let negative = operators.last() == Some(&UnaryOperator::Negate);
+          if negative {
+            operators.pop();
+          }
+          Expr::LiteralValue(LiteralValue::NumericLiteral(number(text, negative)?))
+        }
+        Operand::Expr(expr) => expr,
+      };
+      // the operator closest to the operand gets applied first
+      Ok(
+        operators
+          .into_iter()
+          .rev()
+          .fold(expr, |expr, op| Expr::unary(op, expr)),
+      )
+    },
+  )
+}
+
+#[test]
+fn test_unary_expr() {
+  let num = |n| Expr::LiteralValue(LiteralValue::NumericLiteral(n));
+  assert_ast(expr(), "-5", num(-5));
+  assert_ast(expr(), "-9223372036854775808", num(i64::MIN));
+  assert_ast(
+    expr(),
+    "- -5 - +5",
+    Expr::binary(
+      BinaryOperator::Subtract,
+      Expr::unary(UnaryOperator::Negate, num(-5)),
+      Expr::unary(UnaryOperator::Plus, num(5)),
+    ),
+  );
+  assert_ast(expr(), "-(5)", Expr::unary(UnaryOperator::Negate, num(5)));
+}
+
+fn primary_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
   use combine::parser::choice::choice;
   choice((
     literal_value().map(Expr::LiteralValue),
+    parameter().map(Expr::Parameter),
+    function_call().map(Expr::FunctionCall),
     column_ident().map(Expr::ColumnIdent),
+    case_expr(),
+    (
+      token(Kind::Exists),
+      token(Kind::LeftParen),
+      select_statement(),
+      token(Kind::RightParen),
+    )
+      .map(|(_, _, query, _)| Expr::Exists(Box::new(query))),
+    (
+      token(Kind::LeftParen),
+      choice((
+        select_statement().map(|query| Expr::Subquery(Box::new(query))),
+        expr(),
+      )),
+      token(Kind::RightParen),
+    )
+      .map(|(_, expr, _)| expr),
   ))
 }
 
-fn literal_value<'a>() -> impl Parser<Input = TokenStream<'a>, Output = LiteralValue<'a>> {
+fn parameter<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Parameter> {
+  token(Kind::Parameter).map(|token| Parameter {
+    text: token.value.to_string(),
+    index: 0,
+  })
+}
+
+fn case_expr<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Expr> {
+  use combine::parser::{choice::optional, repeat::many1};
+  (
+    token(Kind::Case),
+    optional(expr()),
+    many1(
+      (token(Kind::When), expr(), token(Kind::Then), expr()).map(|(_, when, _, then)| (when, then)),
+    ),
+    optional((token(Kind::Else), expr()).map(|(_, expr)| expr)),
+    token(Kind::End),
+  )
+    .map(|(_, operand, when_then, else_expr, _)| Expr::Case {
+      operand: operand.map(Box::new),
+      when_then,
+      else_expr: else_expr.map(Box::new),
+    })
+}
+
+#[test]
+fn test_case_expr() {
+  let num = |n| Expr::LiteralValue(LiteralValue::NumericLiteral(n));
+  let col = |name| {
+    Expr::ColumnIdent(ColumnIdent {
+      column: Ident::new(name),
+      table: None,
+    })
+  };
+  assert_ast(
+    expr(),
+    "CASE WHEN a > 1 THEN 1 WHEN a < 0 THEN 2 END",
+    Expr::Case {
+      operand: None,
+      when_then: vec![
+        (
+          Expr::binary(BinaryOperator::GreaterThan, col("a"), num(1)),
+          num(1),
+        ),
+        (
+          Expr::binary(BinaryOperator::LessThan, col("a"), num(0)),
+          num(2),
+        ),
+      ],
+      else_expr: None,
+    },
+  );
+  assert_ast(
+    expr(),
+    "CASE a WHEN 1 THEN b ELSE -1 END",
+    Expr::Case {
+      operand: Some(Box::new(col("a"))),
+      when_then: vec![(num(1), col("b"))],
+      else_expr: Some(Box::new(num(-1))),
+    },
+  );
+}
+
+fn function_call<'a>() -> impl Parser<Input = TokenStream<'a>, Output = FunctionCall> {
+  use combine::parser::{choice::choice, combinator::attempt, repeat::sep_by};
+  (
+    attempt((ident(), token(Kind::LeftParen))),
+    choice((
+      token(Kind::Asterisk).map(|_| FunctionArgs::Asterisk),
+      sep_by(expr(), token(Kind::Comma)).map(FunctionArgs::Exprs),
+    )),
+    token(Kind::RightParen),
+  )
+    .map(|((name, _), args, _)| FunctionCall { name, args })
+}
+
+#[test]
+fn test_function_call() {
+  assert_ast(
+    function_call(),
+    "count(*)",
+    FunctionCall {
+      name: Ident::new("count"),
+      args: FunctionArgs::Asterisk,
+    },
+  );
+  assert_ast(
+    function_call(),
+    "f()",
+    FunctionCall {
+      name: Ident::new("f"),
+      args: FunctionArgs::Exprs(vec![]),
+    },
+  );
+  assert_ast(
+    function_call(),
+    "max(price * 2, 1)",
+    FunctionCall {
+      name: Ident::new("max"),
+      args: FunctionArgs::Exprs(vec![
+        Expr::binary(
+          BinaryOperator::Multiply,
+          Expr::ColumnIdent(ColumnIdent {
+            column: Ident::new("price"),
+            table: None,
+          }),
+          Expr::LiteralValue(LiteralValue::NumericLiteral(2)),
+        ),
+        Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+      ]),
+    },
+  );
+}
+
+#[test]
+fn test_expr_precedence() {
+  let num = |n| Expr::LiteralValue(LiteralValue::NumericLiteral(n));
+  let col = |name| {
+    Expr::ColumnIdent(ColumnIdent {
+      column: Ident::new(name),
+      table: None,
+    })
+  };
+  assert_ast(
+    expr(),
+    "1 + 2 * 3 - 4",
+    Expr::binary(
+      BinaryOperator::Subtract,
+      Expr::binary(
+        BinaryOperator::Add,
+        num(1),
+        Expr::binary(BinaryOperator::Multiply, num(2), num(3)),
+      ),
+      num(4),
+    ),
+  );
+  assert_ast(
+    expr(),
+    "-(1 + 2) * 3",
+    Expr::binary(
+      BinaryOperator::Multiply,
+      Expr::unary(
+        UnaryOperator::Negate,
+        Expr::binary(BinaryOperator::Add, num(1), num(2)),
+      ),
+      num(3),
+    ),
+  );
+  assert_ast(
+    expr(),
+    "a = 1 AND b <> 2 OR NOT c < 3",
+    Expr::binary(
+      BinaryOperator::Or,
+      Expr::binary(
+        BinaryOperator::And,
+        Expr::binary(BinaryOperator::Equal, col("a"), num(1)),
+        Expr::binary(BinaryOperator::NotEqual, col("b"), num(2)),
+      ),
+      Expr::unary(
+        UnaryOperator::Not,
+        Expr::binary(BinaryOperator::LessThan, col("c"), num(3)),
+      ),
+    ),
+  );
+  assert_ast(
+    expr(),
+    "a IS NOT NULL",
+    Expr::binary(
+      BinaryOperator::IsNot,
+      col("a"),
+      Expr::LiteralValue(LiteralValue::Null),
+    ),
+  );
+}
+
+#[test]
+fn test_subquery_expr() {
+  let col = |name| {
+    Expr::ColumnIdent(ColumnIdent {
+      column: Ident::new(name),
+      table: None,
+    })
+  };
+  let select_id_from = |table| {
+    Box::new(SelectStatement {
+      with: None,
+      core: SelectCore {
+        distinct: false,
+        columns: vec![ResultColumn::Expr {
+          value: col("id"),
+          alias: None,
+        }],
+        from: Some(JoinClause {
+          table: TableOrSubquery::Table {
+            name: Ident::new(table),
+            alias: None,
+          },
+          joins: vec![],
+        }),
+        where_clause: None,
+        group_by: vec![],
+        having: None,
+      },
+      compound: vec![],
+      order_by: vec![],
+      limit: None,
+    })
+  };
+  assert_ast(
+    expr(),
+    "id NOT IN (SELECT id FROM admins) AND id IN (1, 2)",
+    Expr::binary(
+      BinaryOperator::And,
+      Expr::In {
+        expr: Box::new(col("id")),
+        not: true,
+        list: InList::Subquery(select_id_from("admins")),
+      },
+      Expr::In {
+        expr: Box::new(col("id")),
+        not: false,
+        list: InList::Exprs(vec![
+          Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+          Expr::LiteralValue(LiteralValue::NumericLiteral(2)),
+        ]),
+      },
+    ),
+  );
+  assert_ast(
+    expr(),
+    "NOT EXISTS (SELECT id FROM posts) = (SELECT id FROM users)",
+    Expr::unary(
+      UnaryOperator::Not,
+      Expr::binary(
+        BinaryOperator::Equal,
+        Expr::Exists(select_id_from("posts")),
+        Expr::Subquery(select_id_from("users")),
+      ),
+    ),
+  );
+  assert_ast(
+    table_or_subquery(),
+    "(SELECT id FROM users) AS u",
+    TableOrSubquery::Subquery {
+      query: select_id_from("users"),
+      alias: Some(Ident::new("u")),
+    },
+  );
+}
+
+fn literal_value<'a>() -> impl Parser<Input = TokenStream<'a>, Output = LiteralValue> {
   use combine::parser::choice::choice;
   choice((
-    numeric_literal().map(LiteralValue::NumericLiteral),
+    numeric_literal(),
     string_literal().map(LiteralValue::StringLiteral),
     blob_literal().map(LiteralValue::BlobLiteral),
+    token(Kind::Null).map(|_| LiteralValue::Null),
   ))
 }
 
 #[test]
 fn test_literal_value() {
   assert_ast(literal_value(), "123", LiteralValue::NumericLiteral(123));
+  assert_ast(
+    literal_value(),
+    "9223372036854775807",
+    LiteralValue::NumericLiteral(i64::MAX),
+  );
+  assert_ast(literal_value(), "0x1F", LiteralValue::NumericLiteral(31));
+  assert_ast(
+    literal_value(),
+    "0XfFfFfFfFfFfFfFfF",
+    LiteralValue::NumericLiteral(-1),
+  );
 }
 
-fn numeric_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = i64> {
-  token(Kind::NumericLiteral).map(|token| token.value.parse::<i64>().unwrap())
+fn numeric_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = LiteralValue> {
+  integer_literal().map(LiteralValue::NumericLiteral)
 }
 
-fn string_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = &'a str> {
-  token(Kind::StringLiteral).map(|token| {
-    // need to strip off the leading and trailing '
-    assert!(token.value.starts_with("'"));
-    assert!(token.value.ends_with("'"));
-    token.value.trim_matches('\'')
-  })
+fn integer_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = i64> {
+  token(Kind::NumericLiteral).and_then(|token| number(token.value, false))
+}
+
+type NumberError<'a> = Error<Token<'a, Kind>, Token<'a, Kind>>;
+
+/// Works out the value of a numeric literal, the same way sqlite does:
+/// hex numbers are the bits of a 64-bit integer (so 0xFFFFFFFFFFFFFFFF is
+/// -1). Anything with a decimal point or an exponent is a real number,
+/// which the database can't store yet, so those are turned away here.
+fn number<'a>(text: &str, negative: bool) -> Result<i64, NumberError<'a>> {
+  let sign = if negative { "-" } else { "" };
+  let out_of_range = || Error::Message(Info::Owned(format!("{}{} is out of range", sign, text)));
+  if text.starts_with("0x") || text.starts_with("0X") {
+    let value = u64::from_str_radix(&text[2..], 16).map_err(|_| out_of_range())? as i64;
+    if negative {
+      value.checked_neg().ok_or_else(out_of_range)
+    } else {
+      Ok(value)
+    }
+  } else if text.contains(&['.', 'e', 'E'][..]) {
+    Err(Error::Message(Info::Owned(format!(
+      "{}{} is a real number, and only integers are supported",
+      sign, text
+    ))))
+  } else {
+    format!("{}{}", sign, text)
+      .parse()
+      .map_err(|_| out_of_range())
+  }
+}
+
+fn string_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = String> {
+  token(Kind::StringLiteral).map(|token| unescape_string(token.value))
+}
+
+/// Strips off the leading and trailing ' of a string, and unescapes any
+/// quotes in between
+fn unescape_string(value: &str) -> String {
+  assert!(value.len() >= 2);
+  assert!(value.starts_with('\''));
+  assert!(value.ends_with('\''));
+  value[1..value.len() - 1].replace("''", "'")
 }
 
 #[test]
 fn test_string_literal() {
-  assert_ast(string_literal(), "'abc'", "abc");
+  assert_ast(string_literal(), "'abc'", "abc".to_string());
+  assert_ast(string_literal(), "''", "".to_string());
+  assert_ast(string_literal(), "'it''s'", "it's".to_string());
+  assert_ast(string_literal(), "''''", "'".to_string());
+  assert_ast(
+    string_literal(),
+    "'hello, wörld\n  ☃'",
+    "hello, wörld\n  ☃".to_string(),
+  );
 }
 
-fn insert_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = InsertStatement<'a>> {
+fn insert_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = InsertStatement> {
   use combine::parser::repeat::sep_by;
 
   (
@@ -209,7 +1179,7 @@ fn insert_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Inser
 }
 
 fn insert_statement_values<'a>(
-) -> impl Parser<Input = TokenStream<'a>, Output = InsertStatementValues<'a>> {
+) -> impl Parser<Input = TokenStream<'a>, Output = InsertStatementValues> {
   use combine::parser::{
     choice::choice,
     combinator::attempt,
@@ -242,7 +1212,7 @@ fn test_insert_statement_values() {
     "VALUE (1, 'a')",
     InsertStatementValues::SingleRow(vec![
       Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
-      Expr::LiteralValue(LiteralValue::StringLiteral("a")),
+      Expr::LiteralValue(LiteralValue::StringLiteral("a".to_string())),
     ]),
   );
   assert_ast(
@@ -251,27 +1221,131 @@ fn test_insert_statement_values() {
     InsertStatementValues::MultipleRows(vec![
       vec![
         Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
-        Expr::LiteralValue(LiteralValue::StringLiteral("a")),
+        Expr::LiteralValue(LiteralValue::StringLiteral("a".to_string())),
       ],
       vec![
         Expr::LiteralValue(LiteralValue::NumericLiteral(2)),
-        Expr::LiteralValue(LiteralValue::StringLiteral("b")),
+        Expr::LiteralValue(LiteralValue::StringLiteral("b".to_string())),
       ],
     ]),
   );
 }
 
-fn blob_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = &'a str> {
-  (token(Kind::X), string_literal()).map(|(_, string)| string)
+fn delete_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = DeleteStatement> {
+  use combine::parser::choice::optional;
+
+  (
+    (token(Kind::Delete), token(Kind::From)),
+    ident(),
+    optional((token(Kind::Where), expr()).map(|(_, expr)| expr)),
+  )
+    .map(|(_, table, where_clause)| DeleteStatement {
+      table,
+      where_clause,
+    })
+}
+
+#[test]
+fn test_delete_statement() {
+  assert_ast(
+    delete_statement(),
+    "DELETE FROM users",
+    DeleteStatement {
+      table: Ident::new("users"),
+      where_clause: None,
+    },
+  );
+  assert_ast(
+    delete_statement(),
+    "delete from users where id = 1",
+    DeleteStatement {
+      table: Ident::new("users"),
+      where_clause: Some(Expr::binary(
+        BinaryOperator::Equal,
+        Expr::ColumnIdent(ColumnIdent {
+          column: Ident::new("id"),
+          table: None,
+        }),
+        Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+      )),
+    },
+  );
+}
+
+fn update_statement<'a>() -> impl Parser<Input = TokenStream<'a>, Output = UpdateStatement> {
+  use combine::parser::{choice::optional, repeat::sep_by1};
+
+  let assignment =
+    (ident(), token(Kind::Equal), expr()).map(|(column, _, value)| Assignment { column, value });
+
+  (
+    token(Kind::Update),
+    ident(),
+    token(Kind::Set),
+    sep_by1(assignment, token(Kind::Comma)),
+    optional((token(Kind::Where), expr()).map(|(_, expr)| expr)),
+  )
+    .map(|(_, table, _, assignments, where_clause)| UpdateStatement {
+      table,
+      assignments,
+      where_clause,
+    })
+}
+
+#[test]
+fn test_update_statement() {
+  assert_ast(
+    update_statement(),
+    "UPDATE users SET active = 0, name = 'banned' WHERE id = 1",
+    UpdateStatement {
+      table: Ident::new("users"),
+      assignments: vec![
+        Assignment {
+          column: Ident::new("active"),
+          value: Expr::LiteralValue(LiteralValue::NumericLiteral(0)),
+        },
+        Assignment {
+          column: Ident::new("name"),
+          value: Expr::LiteralValue(LiteralValue::StringLiteral("banned".to_string())),
+        },
+      ],
+      where_clause: Some(Expr::binary(
+        BinaryOperator::Equal,
+        Expr::ColumnIdent(ColumnIdent {
+          column: Ident::new("id"),
+          table: None,
+        }),
+        Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+      )),
+    },
+  );
+}
+
+fn blob_literal<'a>() -> impl Parser<Input = TokenStream<'a>, Output = String> {
+  // the x is part of the token, so that x can still be used as a name
+  token(Kind::BlobLiteral).map(|token| unescape_string(&token.value[1..]))
 }
 
 #[test]
 fn test_blob_literal() {
-  assert_ast(blob_literal(), "x'abc'", "abc")
+  assert_ast(blob_literal(), "x'abc'", "abc".to_string());
+  assert_ast(blob_literal(), "X''", "".to_string());
+  assert_ast(blob_literal(), "x'a''b'", "a'b".to_string());
 }
 
-fn ident<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Ident<'a>> {
-  token(Kind::Ident).map(|val| Ident::new(val.value))
+fn ident<'a>() -> impl Parser<Input = TokenStream<'a>, Output = Ident> {
+  token(Kind::Ident).map(|val| {
+    // quoted identifiers need their quotes taken off, and any quotes in
+    // between unescaped
+    let value = val.value;
+    match value.chars().next() {
+      Some(quote @ '"') | Some(quote @ '`') => {
+        let doubled = format!("{}{}", quote, quote);
+        Ident::quoted(&value[1..value.len() - 1].replace(&doubled, &quote.to_string()))
+      }
+      _ => Ident::new(value),
+    }
+  })
 }
 
 #[cfg(test)]
@@ -281,14 +1355,22 @@ mod tests {
   #[test]
   fn test_ident() {
     assert_ast(ident(), "abcd", Ident::new("abcd"));
+    assert_ast(ident(), "Größe", Ident::new("größe"));
+    assert_ast(ident(), "USERS", Ident::new("users"));
+    assert_ast(ident(), r#""USERS""#, Ident::quoted("USERS"));
+    assert_ast(ident(), r#""User Name""#, Ident::quoted("User Name"));
+    assert_ast(ident(), r#""say ""hi""""#, Ident::quoted(r#"say "hi""#));
+    assert_ast(ident(), "`value`", Ident::quoted("value"));
+    assert_ast(ident(), "`a``b`", Ident::quoted("a`b"));
   }
 
   #[test]
   fn test_select_statement() {
     assert_ast(
-      select_statement(),
+      select_core(),
       "select *, users.*, users.username as name, username from users",
-      SelectStatement {
+      SelectCore {
+        distinct: false,
         columns: vec![
           ResultColumn::Asterisk,
           ResultColumn::TableAsterisk(Ident::new("users")),
@@ -307,7 +1389,143 @@ mod tests {
             alias: None,
           },
         ],
-        table: Some(Ident::new("users")),
+        from: Some(JoinClause {
+          table: TableOrSubquery::Table {
+            name: Ident::new("users"),
+            alias: None,
+          },
+          joins: vec![],
+        }),
+        where_clause: None,
+        group_by: vec![],
+        having: None,
+      },
+    )
+  }
+
+  #[test]
+  fn test_select_statement_where() {
+    assert_ast(
+      select_core(),
+      "select all id from users where active",
+      SelectCore {
+        distinct: false,
+        columns: vec![ResultColumn::Expr {
+          value: Expr::ColumnIdent(ColumnIdent {
+            column: Ident::new("id"),
+            table: None,
+          }),
+          alias: None,
+        }],
+        from: Some(JoinClause {
+          table: TableOrSubquery::Table {
+            name: Ident::new("users"),
+            alias: None,
+          },
+          joins: vec![],
+        }),
+        where_clause: Some(Expr::ColumnIdent(ColumnIdent {
+          column: Ident::new("active"),
+          table: None,
+        })),
+        group_by: vec![],
+        having: None,
+      },
+    )
+  }
+
+  #[test]
+  fn test_select_statement_group_by() {
+    let team = || {
+      Expr::ColumnIdent(ColumnIdent {
+        column: Ident::new("team"),
+        table: None,
+      })
+    };
+    let count = || {
+      Expr::FunctionCall(FunctionCall {
+        name: Ident::new("count"),
+        args: FunctionArgs::Asterisk,
+      })
+    };
+    assert_ast(
+      select_core(),
+      "select distinct team, count(*) from users group by team having count(*) > 1",
+      SelectCore {
+        distinct: true,
+        columns: vec![
+          ResultColumn::Expr {
+            value: team(),
+            alias: None,
+          },
+          ResultColumn::Expr {
+            value: count(),
+            alias: None,
+          },
+        ],
+        from: Some(JoinClause {
+          table: TableOrSubquery::Table {
+            name: Ident::new("users"),
+            alias: None,
+          },
+          joins: vec![],
+        }),
+        where_clause: None,
+        group_by: vec![team()],
+        having: Some(Expr::binary(
+          BinaryOperator::GreaterThan,
+          count(),
+          Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+        )),
+      },
+    )
+  }
+
+  #[test]
+  fn test_compound_select_statement() {
+    let select = |n| SelectCore {
+      distinct: false,
+      columns: vec![ResultColumn::Expr {
+        value: Expr::LiteralValue(LiteralValue::NumericLiteral(n)),
+        alias: None,
+      }],
+      from: None,
+      where_clause: None,
+      group_by: vec![],
+      having: None,
+    };
+    assert_ast(
+      select_statement(),
+      "select 1 union select 2 union all select 3 intersect select 4 except select 5 order by 1 limit 2",
+      SelectStatement {
+        with: None,
+        core: select(1),
+        compound: vec![
+          CompoundSelect {
+            operator: CompoundOperator::Union,
+            core: select(2),
+          },
+          CompoundSelect {
+            operator: CompoundOperator::UnionAll,
+            core: select(3),
+          },
+          CompoundSelect {
+            operator: CompoundOperator::Intersect,
+            core: select(4),
+          },
+          CompoundSelect {
+            operator: CompoundOperator::Except,
+            core: select(5),
+          },
+        ],
+        order_by: vec![OrderingTerm {
+          expr: Expr::LiteralValue(LiteralValue::NumericLiteral(1)),
+          order: SortOrder::Asc,
+        }],
+        limit: Some(Limit {
+          limit: Expr::LiteralValue(LiteralValue::NumericLiteral(2)),
+          offset: None,
+        }),
       },
     )
   }
@@ -352,13 +1570,22 @@ impl<'a> Parser for TokenMatch<'a> {
   type PartialState = ();
 
   fn parse_lazy(&mut self, input: &mut Self::Input) -> ConsumedResult<Self::Output, Self::Input> {
-    satisfy(|c: Token<'a, Kind>| c.kind == self.kind).parse_lazy(input)
+    match satisfy(|c: Token<'a, Kind>| c.kind == self.kind).parse_lazy(input) {
+      // combine only asks some of the parsers that could have matched to
+      // add what they expected, so every token says so up front instead
+      FastResult::EmptyErr(mut error) => {
+        self.add_error(&mut error);
+        FastResult::EmptyErr(error)
+      }
+      result => result,
+    }
   }
 
   fn add_error(&mut self, error: &mut Tracked<Errors<Token<'a, Kind>, Token<'a, Kind>, Pos>>) {
-    error
-      .error
-      .add_error(Error::Expected(Info::Owned(format!("{:?}", self.kind))));
+    error.error.add_error(Error::Expected(Info::Token(Token {
+      kind: self.kind,
+      value: "",
+    })));
   }
 }
 
@@ -373,7 +1600,6 @@ fn token<'a>(kind: Kind) -> TokenMatch<'a> {
 }
 
 pub type TokenStream<'a> = tokenizer::TokenStream<'a, Sql>;
-pub type ParseResult<'a, T> = combine::ParseResult<T, TokenStream<'a>>;
 
 #[cfg(test)]
 fn assert_ast<'a, T: PartialEq + std::fmt::Debug>(
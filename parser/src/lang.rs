@@ -1,4 +1,5 @@
 use crate::tokenizer::{Keyword, Language, Punctuation, RegexToken};
+use std::fmt;
 
 /*
  * we want to start out being able to parse the following SQL:
@@ -18,24 +19,70 @@ pub struct Sql(pub(crate) ());
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Kind {
   Create,
+  Drop,
+  Alter,
+  Add,
+  Column,
+  Rename,
+  To,
+  If,
+  Exists,
   Table,
   Integer,
-
   Varchar,
+
   Insert,
   Into,
   Value,
   Values,
+  Delete,
+  Update,
+  Set,
+  With,
+  Recursive,
   Select,
+  Distinct,
+  All,
+  Union,
+  Intersect,
+  Except,
   From,
+  Where,
+  Order,
+  Group,
+  Having,
+  By,
+  Asc,
+  Desc,
+  Limit,
+  Offset,
   As,
+  Join,
+  Inner,
+  Left,
+  Outer,
+  Cross,
+  On,
+
+  And,
+  Or,
+  Not,
+  Is,
+  In,
+  Case,
+  When,
+  Then,
+  Else,
+  End,
 
   Ident,
-  X,
   Null,
 
   StringLiteral,
+  BlobLiteral,
   NumericLiteral,
+  /// ?, ?NNN or :name
+  Parameter,
 
   Comma,
   Period,
@@ -43,15 +90,60 @@ pub enum Kind {
   RightParen,
   SemiColon,
   Asterisk,
+  Plus,
+  Minus,
+  Slash,
+  Percent,
+  LessThan,
+  LessThanOrEqual,
+  GreaterThan,
+  GreaterThanOrEqual,
+  Equal,
+  DoubleEqual,
+  NotEqual,
+  LessThanGreaterThan,
 }
+/// How a kind of token is described in a parse error
+impl fmt::Display for Kind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Kind::Ident => write!(f, "an identifier"),
+      Kind::StringLiteral => write!(f, "a string"),
+      Kind::NumericLiteral => write!(f, "a number"),
+      Kind::Parameter => write!(f, "a parameter"),
+      Kind::BlobLiteral => write!(f, "a blob"),
+      kind => {
+        let keyword = Sql::keywords()
+          .into_iter()
+          .find(|keyword| keyword.token == *kind);
+        let punctuation = Sql::punctuation()
+          .into_iter()
+          .find(|punctuation| punctuation.token == *kind);
+        match (keyword, punctuation) {
+          (Some(keyword), _) => write!(f, "{}", keyword.text.to_uppercase()),
+          (None, Some(punctuation)) => write!(f, "{:?}", punctuation.text),
+          (None, None) => write!(f, "{:?}", kind),
+        }
+      }
+    }
+  }
+}
+
 impl Language for Sql {
   type Kind = Kind;
   fn keywords() -> Vec<Keyword<Kind>> {
     vec![
       ("as", Kind::As),
       ("null", Kind::Null),
-      ("x", Kind::X), // used to mark blob literals
       ("create", Kind::Create),
+      ("drop", Kind::Drop),
+      ("alter", Kind::Alter),
+      ("add", Kind::Add),
+      ("column", Kind::Column),
+      ("rename", Kind::Rename),
+      ("to", Kind::To),
+      ("if", Kind::If),
+      ("exists", Kind::Exists),
       ("table", Kind::Table),
       ("integer", Kind::Integer),
       ("varchar", Kind::Varchar),
@@ -59,8 +151,43 @@ impl Language for Sql {
       ("into", Kind::Into),
       ("values", Kind::Values),
       ("value", Kind::Value),
+      ("delete", Kind::Delete),
+      ("update", Kind::Update),
+      ("set", Kind::Set),
+      ("with", Kind::With),
+      ("recursive", Kind::Recursive),
       ("select", Kind::Select),
+      ("distinct", Kind::Distinct),
+      ("all", Kind::All),
+      ("union", Kind::Union),
+      ("intersect", Kind::Intersect),
+      ("except", Kind::Except),
       ("from", Kind::From),
+      ("where", Kind::Where),
+      ("order", Kind::Order),
+      ("group", Kind::Group),
+      ("having", Kind::Having),
+      ("by", Kind::By),
+      ("asc", Kind::Asc),
+      ("desc", Kind::Desc),
+      ("limit", Kind::Limit),
+      ("offset", Kind::Offset),
+      ("join", Kind::Join),
+      ("inner", Kind::Inner),
+      ("left", Kind::Left),
+      ("outer", Kind::Outer),
+      ("cross", Kind::Cross),
+      ("on", Kind::On),
+      ("and", Kind::And),
+      ("or", Kind::Or),
+      ("not", Kind::Not),
+      ("is", Kind::Is),
+      ("in", Kind::In),
+      ("case", Kind::Case),
+      ("when", Kind::When),
+      ("then", Kind::Then),
+      ("else", Kind::Else),
+      ("end", Kind::End),
     ]
     .into_iter()
     .map(|(text, kind)| Keyword::create(text, kind).set_case_sensitive(false))
@@ -74,6 +201,20 @@ impl Language for Sql {
       (")", Kind::RightParen),
       (";", Kind::SemiColon),
       ("*", Kind::Asterisk),
+      ("+", Kind::Plus),
+      ("-", Kind::Minus),
+      ("/", Kind::Slash),
+      ("%", Kind::Percent),
+      // the longer operators need to come first, so that `<=` isn't
+      // read as `<` followed by `=`
+      ("<=", Kind::LessThanOrEqual),
+      ("<>", Kind::LessThanGreaterThan),
+      ("<", Kind::LessThan),
+      (">=", Kind::GreaterThanOrEqual),
+      (">", Kind::GreaterThan),
+      ("==", Kind::DoubleEqual),
+      ("=", Kind::Equal),
+      ("!=", Kind::NotEqual),
     ]
     .into_iter()
     .map(|(text, kind)| Punctuation::create(text, kind))
@@ -81,17 +222,45 @@ impl Language for Sql {
   }
   fn regexes() -> Vec<RegexToken<Kind>> {
     vec![
-      RegexToken::create("[a-zA-Z_][a-zA-Z_0-9]*", Kind::Ident),
-      RegexToken::create(r"'\w*?'", Kind::StringLiteral),
-      RegexToken::create(r"[0-9]+", Kind::NumericLiteral),
+      // this has to come before identifiers, or the x would be one
+      RegexToken::create(r"[xX]'([^']|'')*'", Kind::BlobLiteral),
+      RegexToken::create(r"[\p{Alphabetic}_][\p{Alphabetic}\p{N}_]*", Kind::Ident),
+      // quoted identifiers can hold anything, with the quote doubled up
+      RegexToken::create(r#""([^"]|"")*""#, Kind::Ident),
+      RegexToken::create(r"`([^`]|``)*`", Kind::Ident),
+      // a quote inside a string is written as two quotes
+      RegexToken::create(r"'([^']|'')*'", Kind::StringLiteral),
+      RegexToken::create(r"0[xX][0-9a-fA-F]+", Kind::NumericLiteral),
+      RegexToken::create(
+        r"([0-9]+(\.[0-9]*)?|\.[0-9]+)([eE][+-]?[0-9]+)?",
+        Kind::NumericLiteral,
+      ),
+      RegexToken::create(
+        r"\?([1-9][0-9]{0,8})?|:[a-zA-Z_][a-zA-Z_0-9]*",
+        Kind::Parameter,
+      ),
     ]
   }
+
+  /// `-- comments` run to the end of the line, and `/* comments */` run
+  /// to the closing `*/` (or the end of the query, like they do in sqlite)
+  fn skip_comments(&self, text: &str) -> Option<usize> {
+    if text.starts_with("--") {
+      return Some(text.find('\n').unwrap_or(text.len()));
+    }
+    text
+      .strip_prefix("/*")
+      .map(|comment| match comment.find("*/") {
+        Some(end) => end + 4,
+        None => text.len(),
+      })
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::tokenizer::TokenStream;
+  use crate::tokenizer::{Pos, TokenStream};
   use combine::easy::Error;
   use combine::{Positioned, StreamOnce};
 
@@ -105,7 +274,7 @@ mod tests {
         Err(e) => panic!("Parse error at {}: {}", s.position(), e),
       }
     }
-    return r;
+    r
   }
   fn tok_typ(s: &str) -> Vec<Kind> {
     let mut r = Vec::new();
@@ -117,7 +286,7 @@ mod tests {
         Err(e) => panic!("Parse error at {}: {}", s.position(), e),
       }
     }
-    return r;
+    r
   }
 
   fn assert_tokens(text: &str, types: &[Kind], tokens: &[&str]) {
@@ -133,6 +302,65 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_operators() {
+    assert_tokens(
+      "a<=b<>c<d>=e>f==g=h!=i",
+      &[
+        Kind::Ident,
+        Kind::LessThanOrEqual,
+        Kind::Ident,
+        Kind::LessThanGreaterThan,
+        Kind::Ident,
+        Kind::LessThan,
+        Kind::Ident,
+        Kind::GreaterThanOrEqual,
+        Kind::Ident,
+        Kind::GreaterThan,
+        Kind::Ident,
+        Kind::DoubleEqual,
+        Kind::Ident,
+        Kind::Equal,
+        Kind::Ident,
+        Kind::NotEqual,
+        Kind::Ident,
+      ],
+      &[
+        "a", "<=", "b", "<>", "c", "<", "d", ">=", "e", ">", "f", "==", "g", "=", "h", "!=", "i",
+      ],
+    );
+  }
+
+  #[test]
+  fn test_parameters() {
+    assert_tokens(
+      "?,?12 :name",
+      &[
+        Kind::Parameter,
+        Kind::Comma,
+        Kind::Parameter,
+        Kind::Parameter,
+      ],
+      &["?", ",", "?12", ":name"],
+    );
+  }
+
+  #[test]
+  fn test_blob_literals() {
+    assert_tokens(
+      "x x'0a' X'' x 'a' xy",
+      &[
+        Kind::Ident,
+        Kind::BlobLiteral,
+        Kind::BlobLiteral,
+        Kind::Ident,
+        Kind::StringLiteral,
+        Kind::Ident,
+      ],
+      &["x", "x'0a'", "X''", "x", "'a'", "xy"],
+    );
+  }
+
   #[test]
   fn test_string_literals() {
     assert_tokens(
@@ -144,5 +372,98 @@ mod tests {
       ],
       &["123", "'a1'", "456"],
     );
+    assert_tokens(
+      "'hello, world!' 'it''s' ''''",
+      &[
+        Kind::StringLiteral,
+        Kind::StringLiteral,
+        Kind::StringLiteral,
+      ],
+      &["'hello, world!'", "'it''s'", "''''"],
+    );
+  }
+
+  #[test]
+  fn test_numbers() {
+    assert_tokens(
+      "1 0x1aF 1.5 2. .5 .5e3 1e10 1.5E-3 3e+2 1-2 t.id",
+      &[
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::NumericLiteral,
+        Kind::Minus,
+        Kind::NumericLiteral,
+        Kind::Ident,
+        Kind::Period,
+        Kind::Ident,
+      ],
+      &[
+        "1", "0x1aF", "1.5", "2.", ".5", ".5e3", "1e10", "1.5E-3", "3e+2", "1", "-", "2", "t", ".",
+        "id",
+      ],
+    );
+  }
+
+  #[test]
+  fn test_idents() {
+    assert_tokens(
+      r#"naïve 名前 _a1 "select" "a ""b""" `x` `a``b`"#,
+      &[
+        Kind::Ident,
+        Kind::Ident,
+        Kind::Ident,
+        Kind::Ident,
+        Kind::Ident,
+        Kind::Ident,
+        Kind::Ident,
+      ],
+      &[
+        "naïve",
+        "名前",
+        "_a1",
+        r#""select""#,
+        r#""a ""b""""#,
+        "`x`",
+        "`a``b`",
+      ],
+    );
+  }
+
+  #[test]
+  fn test_comments() {
+    assert_tokens(
+      "-- leading comment\nselect 1 -- trailing comment\n/* block\n * comment */-2/**/ -- at the end",
+      &[Kind::Select, Kind::NumericLiteral, Kind::Minus, Kind::NumericLiteral],
+      &["select", "1", "-", "2"],
+    );
+    assert_tokens("a /* unterminated", &[Kind::Ident], &["a"]);
+    assert_tokens("'--' /* '*/", &[Kind::StringLiteral], &["'--'"]);
+
+    let mut s = TokenStream::new(Sql(()), "/* ü\n ☃ */ a -- ü\n  /*\n*/ b");
+    assert_eq!(s.position(), Pos { line: 2, column: 7 });
+    assert_eq!(s.uncons().unwrap().value, "a");
+    assert_eq!(s.position(), Pos { line: 4, column: 4 });
+  }
+
+  #[test]
+  fn test_multiline_strings() {
+    let mut s = TokenStream::new(Sql(()), "'héllo\nwörld' 'ü'");
+    assert_eq!(s.uncons().unwrap().value, "'héllo\nwörld'");
+    assert_eq!(s.position(), Pos { line: 2, column: 8 });
+    assert_eq!(s.uncons().unwrap().value, "'ü'");
+    assert_eq!(
+      s.position(),
+      Pos {
+        line: 2,
+        column: 11
+      }
+    );
   }
 }
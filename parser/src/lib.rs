@@ -1,25 +1,62 @@
+#[macro_use]
+extern crate combine;
+
 mod ast;
+mod error;
 mod grammar;
 mod lang;
+mod parameters;
 mod tokenizer;
 
 pub use self::ast::*;
-use self::lang::{Kind, Sql};
+pub use self::error::{ParseError, Unexpected};
+pub use self::lang::Kind;
+use self::lang::Sql;
+pub use self::tokenizer::Pos;
 
 use self::grammar::parse;
+use std::fmt;
 
 #[derive(Debug)]
-pub enum AstError<'a> {
-  ParseError(crate::grammar::ParseError<'a>),
+pub enum AstError {
+  ParseError(ParseError),
 }
 
-impl<'a> From<crate::grammar::ParseError<'a>> for AstError<'a> {
-  fn from(err: crate::grammar::ParseError<'a>) -> AstError<'a> {
+impl From<ParseError> for AstError {
+  fn from(err: ParseError) -> AstError {
     AstError::ParseError(err)
   }
 }
 
-pub fn process_query<'a>(text: &'a str) -> Result<Vec<crate::ast::Statement<'a>>, AstError<'a>> {
-  let ast = parse(text)?;
-  Ok(ast)
+impl fmt::Display for AstError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AstError::ParseError(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for AstError {}
+
+pub fn process_query(text: &str) -> Result<Vec<crate::ast::Statement>, AstError> {
+  Ok(prepare_query(text)?.statements)
+}
+
+/// A query that's been parsed, along with the parameters that need to
+/// have values bound to them before it can be run
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedQuery {
+  pub statements: Vec<crate::ast::Statement>,
+  /// The name of each parameter, by number (starting from 1). Parameters
+  /// written as `?` or `?NNN` don't have a name.
+  pub parameters: Vec<Option<String>>,
+}
+
+pub fn prepare_query(text: &str) -> Result<PreparedQuery, AstError> {
+  let mut statements = parse(text)?;
+  let parameters = self::parameters::number_parameters(&mut statements);
+  Ok(PreparedQuery {
+    statements,
+    parameters,
+  })
 }
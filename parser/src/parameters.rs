@@ -0,0 +1,182 @@
+//! Parameters can only be numbered once the whole query has been parsed,
+//! since the number of a `?` depends on every parameter written before it.
+
+use crate::ast::*;
+
+/// Numbers every parameter in `statements`, in the order they were
+/// written. Returns the name of each parameter by number (starting from
+/// 1), which is `None` for the ones written as `?` or `?NNN`.
+pub fn number_parameters(statements: &mut [Statement]) -> Vec<Option<String>> {
+  let mut names: Vec<Option<String>> = vec![];
+  let mut number = |parameter: &mut Parameter| {
+    let text = parameter.text.as_str();
+    let index = if text == "?" {
+      names.len() + 1
+    } else if let Some(number) = text.strip_prefix('?') {
+      // the tokenizer only lets through numbers that fit
+      number.parse::<usize>().unwrap()
+    } else {
+      match names.iter().position(|name| name.as_deref() == Some(text)) {
+        Some(idx) => idx + 1,
+        None => names.len() + 1,
+      }
+    };
+    if index > names.len() {
+      names.resize(index, None);
+    }
+    if text.starts_with(':') {
+      names[index - 1] = Some(text.to_string());
+    }
+    parameter.index = index;
+  };
+  for statement in statements.iter_mut() {
+    statement_parameters(statement, &mut number);
+  }
+  names
+}
+
+type Visit<'v> = &'v mut dyn FnMut(&mut Parameter);
+
+fn statement_parameters(statement: &mut Statement, f: Visit<'_>) {
+  match statement {
+    Statement::Select(select) => select_parameters(select, f),
+    Statement::Insert(insert) => match &mut insert.values {
+      InsertStatementValues::SingleRow(row) => exprs_parameters(row, f),
+      InsertStatementValues::MultipleRows(rows) => {
+        for row in rows.iter_mut() {
+          exprs_parameters(row, f);
+        }
+      }
+    },
+    Statement::Delete(delete) => optional_expr_parameters(&mut delete.where_clause, f),
+    Statement::Update(update) => {
+      for assignment in update.assignments.iter_mut() {
+        expr_parameters(&mut assignment.value, f);
+      }
+      optional_expr_parameters(&mut update.where_clause, f);
+    }
+    Statement::CreateTable(_) | Statement::DropTable(_) | Statement::AlterTable(_) => {}
+  }
+}
+
+fn select_parameters(select: &mut SelectStatement, f: Visit<'_>) {
+  if let Some(with) = &mut select.with {
+    for cte in with.ctes.iter_mut() {
+      select_parameters(&mut cte.query, f);
+    }
+  }
+  select_core_parameters(&mut select.core, f);
+  for compound in select.compound.iter_mut() {
+    select_core_parameters(&mut compound.core, f);
+  }
+  for term in select.order_by.iter_mut() {
+    expr_parameters(&mut term.expr, f);
+  }
+  if let Some(limit) = &mut select.limit {
+    expr_parameters(&mut limit.limit, f);
+    optional_expr_parameters(&mut limit.offset, f);
+  }
+}
+
+fn select_core_parameters(core: &mut SelectCore, f: Visit<'_>) {
+  for column in core.columns.iter_mut() {
+    if let ResultColumn::Expr { value, .. } = column {
+      expr_parameters(value, f);
+    }
+  }
+  if let Some(from) = &mut core.from {
+    table_parameters(&mut from.table, f);
+    for join in from.joins.iter_mut() {
+      table_parameters(&mut join.table, f);
+      optional_expr_parameters(&mut join.constraint, f);
+    }
+  }
+  optional_expr_parameters(&mut core.where_clause, f);
+  exprs_parameters(&mut core.group_by, f);
+  optional_expr_parameters(&mut core.having, f);
+}
+
+fn table_parameters(table: &mut TableOrSubquery, f: Visit<'_>) {
+  if let TableOrSubquery::Subquery { query, .. } = table {
+    select_parameters(query, f);
+  }
+}
+
+fn exprs_parameters(exprs: &mut [Expr], f: Visit<'_>) {
+  for expr in exprs.iter_mut() {
+    expr_parameters(expr, f);
+  }
+}
+
+fn optional_expr_parameters(expr: &mut Option<Expr>, f: Visit<'_>) {
+  if let Some(expr) = expr {
+    expr_parameters(expr, f);
+  }
+}
+
+fn expr_parameters(expr: &mut Expr, f: Visit<'_>) {
+  match expr {
+    Expr::Parameter(parameter) => f(parameter),
+    Expr::LiteralValue(_) | Expr::ColumnIdent(_) => {}
+    Expr::Unary { expr, .. } => expr_parameters(expr, f),
+    Expr::Binary { lhs, rhs, .. } => {
+      expr_parameters(lhs, f);
+      expr_parameters(rhs, f);
+    }
+    Expr::FunctionCall(call) => {
+      if let FunctionArgs::Exprs(args) = &mut call.args {
+        exprs_parameters(args, f);
+      }
+    }
+    Expr::In { expr, list, .. } => {
+      expr_parameters(expr, f);
+      match list {
+        InList::Exprs(list) => exprs_parameters(list, f),
+        InList::Subquery(query) => select_parameters(query, f),
+      }
+    }
+    Expr::Exists(query) | Expr::Subquery(query) => select_parameters(query, f),
+    Expr::Case {
+      operand,
+      when_then,
+      else_expr,
+    } => {
+      if let Some(operand) = operand {
+        expr_parameters(operand, f);
+      }
+      for (when, then) in when_then.iter_mut() {
+        expr_parameters(when, f);
+        expr_parameters(then, f);
+      }
+      if let Some(else_expr) = else_expr {
+        expr_parameters(else_expr, f);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::prepare_query;
+
+  #[test]
+  fn test_number_parameters() {
+    let query = prepare_query(
+      "select ?, :a, ?5 from t where b = ? and c = :a;
+      update t set b = :b where c = ?1;",
+    )
+    .unwrap();
+    assert_eq!(
+      query.parameters,
+      vec![
+        None,
+        Some(":a".to_string()),
+        None,
+        None,
+        None,
+        None,
+        Some(":b".to_string())
+      ]
+    );
+  }
+}
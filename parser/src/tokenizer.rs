@@ -26,6 +26,9 @@ impl fmt::Display for Pos {
   }
 }
 
+/// The error produced when the tokenizer can't make sense of the input
+type TokenError<'a, K> = Error<Token<'a, K>, Token<'a, K>>;
+
 /// A token in the grammar.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Token<'a, K> {
@@ -136,7 +139,7 @@ impl<T> Keyword<T> {
   }
   pub fn set_case_sensitive(mut self, is_sensitive: bool) -> Keyword<T> {
     self.is_case_sensitive = is_sensitive;
-    return self;
+    self
   }
 }
 
@@ -161,7 +164,8 @@ pub struct RegexToken<T> {
 impl<T> RegexToken<T> {
   pub fn create(regex: &'static str, token: T) -> Self {
     Self {
-      regex: regex::Regex::new(&format!("^{}", regex)).unwrap(),
+      // the group keeps every alternative in the regex anchored to the start
+      regex: regex::Regex::new(&format!("^(?:{})", regex)).unwrap(),
       token,
     }
   }
@@ -196,33 +200,36 @@ impl<'a, L: Language> TokenStream<'a, L> {
 
   pub fn next_char(&self) -> Option<char> {
     let mut iter = self.get_str().chars();
-    return iter.next();
+    iter.next()
   }
 
   /// Helper function for swallowing a single character.
   ///
   /// Returns the character that was swallowing
   pub fn swallow_token(&mut self) -> Option<char> {
-    return self.next_char().map(|val| match val {
+    self.next_char().map(|val| match val {
       '\n' => {
         self.next_line();
         val
       }
       _ => {
-        self.off += 1;
+        self.off += val.len_utf8();
         self.position.column += 1;
         val
       }
-    });
+    })
   }
 
-  fn swallow_n_tokens(&mut self, num: usize) -> usize {
-    for i in 0..num {
+  /// Swallows the next `len` bytes, one character at a time so that the
+  /// position stays correct. Returns how many bytes were swallowed.
+  fn swallow_n_tokens(&mut self, len: usize) -> usize {
+    let start = self.off;
+    while self.off < start + len {
       if self.swallow_token().is_none() {
-        return i;
+        break;
       }
     }
-    return num;
+    self.off - start
   }
 
   /// Get the current string of the TokenStream
@@ -243,20 +250,32 @@ impl<'a, L: Language> TokenStream<'a, L> {
   fn peek_keyword(&self) -> Option<Keyword<L::Kind>> {
     let iter = self.get_str();
     for key in self.keywords.iter() {
-      if !key.is_case_sensitive {
+      let matches = if !key.is_case_sensitive {
         // Pull off key.length tokens from the iter
-        if let Some(next_tokens) = iter.get(0..key.text.len()) {
-          if next_tokens.to_lowercase() == key.text {
-            return Some(key.clone());
-          }
+        match iter.get(0..key.text.len()) {
+          Some(next_tokens) => next_tokens.to_lowercase() == key.text,
+          None => false,
         }
-      } else if iter.starts_with(key.text) {
+      } else {
+        iter.starts_with(key.text)
+      };
+      // A keyword has to be a whole word, otherwise `variable` would
+      // come out as `var` followed by `iable`
+      if matches && !Self::continues_word(key.text, &iter[key.text.len()..]) {
         return Some(key.clone());
       }
     }
     None
   }
 
+  fn continues_word(word: &str, rest: &str) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    match (word.chars().last(), rest.chars().next()) {
+      (Some(last), Some(next)) => is_word_char(last) && is_word_char(next),
+      _ => false,
+    }
+  }
+
   fn peek_regexes<'b>(&self, text: &'b str) -> Option<(RegexToken<L::Kind>, regex::Match<'b>)> {
     for regextoken in self.regexes.iter() {
       if let Some(reg_match) = regextoken.regex.find(text) {
@@ -267,9 +286,7 @@ impl<'a, L: Language> TokenStream<'a, L> {
     None
   }
 
-  fn peek_token(
-    &mut self,
-  ) -> Result<(L::Kind, usize), Error<Token<'a, L::Kind>, Token<'a, L::Kind>>> {
+  fn peek_token(&mut self) -> Result<(L::Kind, usize), TokenError<'a, L::Kind>> {
     let mut iter = self.buf[self.off..].char_indices();
     /*
      * Eagerly handle EOF.
@@ -280,9 +297,17 @@ impl<'a, L: Language> TokenStream<'a, L> {
     };
 
     if let Some(punc) = self.peek_punctuation() {
-      let length = punc.text.len();
-      self.swallow_n_tokens(length);
-      return Ok((punc.token, length));
+      // punctuation can also be the start of a longer token, like the `.`
+      // at the start of `.5`, and the longer token wins
+      let is_longest = match self.peek_regexes(self.get_str()) {
+        Some((_, regex_match)) => regex_match.as_str().len() <= punc.text.len(),
+        None => true,
+      };
+      if is_longest {
+        let length = punc.text.len();
+        self.swallow_n_tokens(length);
+        return Ok((punc.token, length));
+      }
     }
 
     if let Some(key) = self.peek_keyword() {
@@ -328,18 +353,18 @@ impl<'a, L: Language> TokenStream<'a, L> {
 
       match cur_char {
         '\u{feff}' | '\r' => {
-          self.off += 1;
+          self.off += cur_char.len_utf8();
           continue;
         }
-        '\t' => {
-          self.position.column += 2;
-          self.off += 1;
-        }
         '\n' => {
           self.position.column = 1;
           self.position.line += 1;
           self.off += 1;
         }
+        '\t' => {
+          self.position.column += 2;
+          self.off += 1;
+        }
         ' ' => {
           self.position.column += 1;
           self.off += 1;
@@ -438,7 +463,7 @@ mod tests {
           break idx + 3;
         }
       };
-      return Some(len);
+      Some(len)
     }
   }
   fn tok_str(s: &str) -> Vec<&str> {
@@ -451,7 +476,7 @@ mod tests {
         Err(e) => panic!("Parse error at {}: {}", s.position(), e),
       }
     }
-    return r;
+    r
   }
   fn tok_typ(s: &str) -> Vec<Kind> {
     let mut r = Vec::new();
@@ -463,7 +488,7 @@ mod tests {
         Err(e) => panic!("Parse error at {}: {}", s.position(), e),
       }
     }
-    return r;
+    r
   }
   use self::Kind::*;
 
@@ -513,6 +538,15 @@ mod tests {
     )
   }
 
+  #[test]
+  fn test_keywords_are_whole_words() {
+    assert_tokens(
+      "variable var programs",
+      &[Ident, Var, Ident],
+      &["variable", "var", "programs"],
+    )
+  }
+
   #[test]
   fn test_comments() {
     assert_tokens(
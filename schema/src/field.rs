@@ -1,5 +1,5 @@
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use crate::SchemaError;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Write};
 
 pub trait Field {
@@ -64,26 +64,26 @@ impl SchemaField {
     &self.name
   }
 
-  pub fn from_column_def<'a, 'b>(
-    column_def: &'b parser::ColumnDef<'a>,
-  ) -> Result<Self, FieldError> {
+  pub fn from_column_def(column_def: &parser::ColumnDef) -> Result<Self, FieldError> {
     use parser::Type;
+    use std::convert::TryFrom;
     let name = column_def.column_name.text().to_string();
     let type_name = &column_def.type_name;
-    match type_name.name {
-      Type::Integer => {
-        let size = type_name.argument.unwrap_or(8);
-        Ok(SchemaField::new(FieldKind::Number(size as u8), name)?)
-      }
-      Type::Blob => {
-        let size = type_name.argument.unwrap_or(100);
-        Ok(SchemaField::new(FieldKind::Blob(size as u64), name)?)
-      }
-      Type::Varchar => {
-        let size = type_name.argument.unwrap_or(128);
-        Ok(SchemaField::new(FieldKind::Str(size as u64), name)?)
-      }
-    }
+    let size = |default: i64| type_name.argument.unwrap_or(default);
+    let invalid_size = |_| FieldError::InvalidSize(size(0));
+    let kind = match type_name.name {
+      Type::Integer => FieldKind::Number(u8::try_from(size(8)).map_err(invalid_size)?),
+      // these already say how big they are, so an argument is only a
+      // display width (like the 11 in mysql's INT(11)) and gets ignored
+      Type::Int => FieldKind::Number(4),
+      Type::SmallInt => FieldKind::Number(2),
+      Type::BigInt => FieldKind::Number(8),
+      Type::Blob => FieldKind::Blob(u64::try_from(size(100)).map_err(invalid_size)?),
+      Type::Varchar | Type::Text => FieldKind::Str(u64::try_from(size(128)).map_err(invalid_size)?),
+      // CHAR on its own is a single character
+      Type::Char => FieldKind::Str(u64::try_from(size(1)).map_err(invalid_size)?),
+    };
+    SchemaField::new(kind, name)
   }
 }
 
@@ -91,6 +91,8 @@ impl SchemaField {
 pub enum FieldError {
   /// Invalid numeric type, returns the number requested
   InvalidNumberType(u8),
+  /// A type's size doesn't fit in the field, returns the size requested
+  InvalidSize(i64),
 }
 
 /// The kind of a field.
@@ -165,7 +167,7 @@ impl FieldKind {
         let size = disk.read_u64::<BigEndian>()?;
         Ok(FieldKind::Str(size))
       }
-      unknown => return Err(SchemaError::UnknownFieldType(unknown)),
+      unknown => Err(SchemaError::UnknownFieldType(unknown)),
     }
   }
 }
@@ -7,25 +7,45 @@ use std::str::Utf8Error;
 #[derive(Debug, Clone)]
 struct RowMeta {
   is_last_row: bool,
+  /// Deleted rows stay on the disk as a tombstone, and get skipped
+  /// whenever the table is read
+  is_deleted: bool,
 }
 
 impl RowMeta {
+  const LAST_ROW_FLAG: u16 = 1;
+  const DELETED_FLAG: u16 = 1 << 1;
+
+  fn new() -> Self {
+    RowMeta {
+      is_last_row: false,
+      is_deleted: false,
+    }
+  }
   fn size() -> usize {
-    2 // 2 bytes for is_last_row (alignment)
+    2 // 2 bytes of flags (alignment)
   }
   fn persist(&self, disk: &mut impl Write) -> io::Result<()> {
-    let is_last_row = if self.is_last_row { 1 } else { 0 };
-    log::debug!("Persisting RowMeta: is_last_row: {}", is_last_row);
-    disk.write_u16::<BigEndian>(is_last_row)?;
+    let mut flags = 0;
+    if self.is_last_row {
+      flags |= Self::LAST_ROW_FLAG;
+    }
+    if self.is_deleted {
+      flags |= Self::DELETED_FLAG;
+    }
+    log::debug!("Persisting RowMeta: flags: {:#b}", flags);
+    disk.write_u16::<BigEndian>(flags)?;
     Ok(())
   }
   fn from_persisted(disk: &mut impl Read) -> Result<Self, RowCellError> {
-    let is_last_row = match disk.read_u16::<BigEndian>()? {
-      0 => false,
-      1 => true,
-      _ => return Err(RowCellError::InvalidRowMeta),
-    };
-    Ok(Self { is_last_row })
+    let flags = disk.read_u16::<BigEndian>()?;
+    if flags & !(Self::LAST_ROW_FLAG | Self::DELETED_FLAG) != 0 {
+      return Err(RowCellError::InvalidRowMeta);
+    }
+    Ok(Self {
+      is_last_row: flags & Self::LAST_ROW_FLAG != 0,
+      is_deleted: flags & Self::DELETED_FLAG != 0,
+    })
   }
 }
 
@@ -33,6 +53,12 @@ impl RowMeta {
 pub struct Row {
   data: Vec<u8>,
   meta: RowMeta,
+  /// Which cells in the row are NULL. NULL cells don't take up
+  /// any space in `data`.
+  ///
+  /// NULLs only ever show up in rows that are produced while running a
+  /// query, so rows read from the disk leave this empty.
+  nulls: Vec<bool>,
 }
 
 impl Row {
@@ -43,17 +69,24 @@ impl Row {
   pub fn is_last_row(&self) -> bool {
     self.meta.is_last_row
   }
+  pub fn is_deleted(&self) -> bool {
+    self.meta.is_deleted
+  }
   pub fn data(&self) -> &[u8] {
     &self.data
   }
   pub fn into_data(self) -> Vec<u8> {
     self.data
   }
+  pub fn is_null(&self, cell_idx: usize) -> bool {
+    self.nulls.get(cell_idx).cloned().unwrap_or(false)
+  }
 
   pub fn from_data(data: Vec<u8>) -> Self {
     Row {
       data,
-      meta: RowMeta { is_last_row: false },
+      meta: RowMeta::new(),
+      nulls: vec![],
     }
   }
 
@@ -63,25 +96,34 @@ impl Row {
     let num_bytes = schema.sizeof_row();
     let mut data = vec![0; num_bytes];
     disk.read_exact(&mut data)?;
-    Ok(Self { data, meta })
+    Ok(Self {
+      data,
+      meta,
+      nulls: vec![],
+    })
   }
 
   pub fn from_cells(cells: Vec<OwnedRowCell>) -> io::Result<Row> {
-    Row::from_cells_impl(cells, RowMeta { is_last_row: false })
+    Row::from_cells_impl(cells, RowMeta::new())
   }
 
   fn from_cells_impl(cells: Vec<OwnedRowCell>, meta: RowMeta) -> io::Result<Row> {
     let mut data = io::Cursor::new(vec![]);
+    let mut nulls = Vec::with_capacity(cells.len());
 
     for cell in cells.iter() {
+      nulls.push(*cell == OwnedRowCell::Null);
       cell.persist(&mut data)?;
     }
 
     let data = data.into_inner();
-    Ok(Row { data, meta })
+    Ok(Row { data, meta, nulls })
   }
   fn insert_sentinal_row(schema: &Schema, disk: &mut impl Write) -> Result<(), RowCellError> {
-    let meta = RowMeta { is_last_row: true };
+    let meta = RowMeta {
+      is_last_row: true,
+      is_deleted: false,
+    };
     meta.persist(disk)?;
     // pre-allocate space for the next row
     disk.write_all(&vec![0; schema.sizeof_row()])?;
@@ -91,20 +133,24 @@ impl Row {
   pub fn as_cells<'a>(&'a self, fields: &[impl Field]) -> Result<Vec<RowCell<'a>>, RowCellError> {
     let mut buf = Vec::with_capacity(fields.len());
     let mut offset = 0;
-    for field in fields.iter() {
+    for (idx, field) in fields.iter().enumerate() {
+      if self.is_null(idx) {
+        buf.push(RowCell::Null);
+        continue;
+      }
       buf.push(RowCell::new(&self.data, field, offset)?);
       offset += field.kind().size();
     }
     Ok(buf)
   }
   pub fn into_cells(self, fields: &[impl Field]) -> Result<Vec<OwnedRowCell>, RowCellError> {
-    let mut buf = Vec::with_capacity(fields.len());
-    let mut offset = 0;
-    for field in fields.iter() {
-      buf.push(OwnedRowCell::from(RowCell::new(&self.data, field, offset)?));
-      offset += field.kind().size();
-    }
-    Ok(buf)
+    Ok(
+      self
+        .as_cells(fields)?
+        .into_iter()
+        .map(OwnedRowCell::from)
+        .collect(),
+    )
   }
 
   fn persist(&self, disk: &mut impl Write) -> Result<(), RowCellError> {
@@ -113,7 +159,9 @@ impl Row {
     Ok(())
   }
 
-  /// Unsafe because this may only be called ONCE per table, at the very beginning when it's created
+  /// # Safety
+  ///
+  /// This may only be called ONCE per table, at the very beginning when it's created
   pub unsafe fn init_table(schema: &Schema, disk: &mut impl Write) -> Result<(), RowCellError> {
     log::debug!(
       "Writing initial sentinal row (Size-Of-Row {})",
@@ -123,7 +171,9 @@ impl Row {
     Ok(())
   }
 
-  /// Unsafe because you must have called `init_table` before calling this function
+  /// # Safety
+  ///
+  /// You must have called `init_table` before calling this function.
   /// Once `Table` is a concept this will go away, but for now the primary abstraction
   /// is rows and we need this
   pub unsafe fn insert_row(
@@ -141,7 +191,7 @@ impl Row {
 
     disk.seek(io::SeekFrom::End(-(size_of_row as i64)))?;
     {
-      let row = Row::from_cells_impl(row, RowMeta { is_last_row: false })?;
+      let row = Row::from_cells_impl(row, RowMeta::new())?;
       log::debug!("-> Writing new row over the old sentinal");
       row.persist(disk)?;
     }
@@ -151,37 +201,97 @@ impl Row {
     Row::insert_sentinal_row(schema, disk)?;
     Ok(())
   }
+
+  /// Overwrites the row at `index` with `row`, keeping it in the same place
+  /// in the table.
+  ///
+  /// # Safety
+  ///
+  /// You must have called `init_table` before calling this function, `index`
+  /// must be the index of a row that was added with `insert_row`, and every
+  /// cell in `row` must have been coerced to the matching field in `schema`.
+  pub unsafe fn update_row(
+    index: u64,
+    row: Vec<OwnedRowCell>,
+    disk: &mut (impl Write + Seek + Read),
+    schema: &Schema,
+  ) -> Result<(), RowCellError> {
+    log::debug!("update_row {}", index);
+    let offset = index * Row::sizeof_row_on_disk(schema) as u64;
+    disk.seek(io::SeekFrom::Start(offset))?;
+    let meta = RowMeta::from_persisted(disk)?;
+    if meta.is_last_row || meta.is_deleted {
+      return Err(RowCellError::InvalidRowMeta);
+    }
+    let row = Row::from_cells_impl(row, meta)?;
+    disk.seek(io::SeekFrom::Start(offset))?;
+    row.persist(disk)?;
+    Ok(())
+  }
+
+  /// Marks the row at `index` as deleted.
+  ///
+  /// The row's data stays where it is, but it will be skipped by anything
+  /// reading the table from now on.
+  ///
+  /// # Safety
+  ///
+  /// You must have called `init_table` before calling this function, and `index`
+  /// must be the index of a row that was added with `insert_row`.
+  pub unsafe fn delete_row(
+    index: u64,
+    disk: &mut (impl Write + Seek + Read),
+    schema: &Schema,
+  ) -> Result<(), RowCellError> {
+    log::debug!("delete_row {}", index);
+    let offset = index * Row::sizeof_row_on_disk(schema) as u64;
+    disk.seek(io::SeekFrom::Start(offset))?;
+    let mut meta = RowMeta::from_persisted(disk)?;
+    if meta.is_last_row {
+      return Err(RowCellError::InvalidRowMeta);
+    }
+    meta.is_deleted = true;
+    disk.seek(io::SeekFrom::Start(offset))?;
+    meta.persist(disk)?;
+    Ok(())
+  }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum OwnedRowCell {
-  Number { value: i64, size: u8 },
-  Str { value: String, max_size: u64 },
+  Number {
+    value: i64,
+    size: u8,
+  },
+  Str {
+    value: String,
+    max_size: u64,
+  },
   Blob(Vec<u8>),
+  /// The absence of a value. Can't be written to the disk (yet)
+  Null,
 }
 
 impl<'a> From<RowCell<'a>> for OwnedRowCell {
   fn from(cell: RowCell<'a>) -> OwnedRowCell {
     match cell {
       RowCell::Blob(data) => OwnedRowCell::Blob(data.to_vec()),
-      RowCell::Number { value, size } => OwnedRowCell::Number {
-        value: value,
-        size: size,
-      },
+      RowCell::Number { value, size } => OwnedRowCell::Number { value, size },
       RowCell::Str { value, max_size } => OwnedRowCell::Str {
         value: value.to_string(),
-        max_size: max_size,
+        max_size,
       },
+      RowCell::Null => OwnedRowCell::Null,
     }
   }
 }
 
 impl OwnedRowCell {
-  pub fn from_ast_expr<'a>(ast: &parser::Expr<'a>) -> Option<OwnedRowCell> {
+  pub fn from_ast_expr(ast: &parser::Expr) -> Option<OwnedRowCell> {
     use parser::{Expr, LiteralValue};
     let literal = match ast {
-      Expr::ColumnIdent(_) => return None,
       Expr::LiteralValue(val) => val,
+      _ => return None,
     };
     match literal {
       LiteralValue::NumericLiteral(value) => Some(OwnedRowCell::Number {
@@ -196,51 +306,57 @@ impl OwnedRowCell {
         Ok(buf) => Some(OwnedRowCell::Blob(buf)),
         Err(_) => None,
       },
+      LiteralValue::Null => Some(OwnedRowCell::Null),
     }
   }
-  pub fn coerce_to(mut self, field: &impl Field) -> Option<OwnedRowCell> {
+  pub fn coerce_to(mut self, field: &impl Field) -> Result<OwnedRowCell, CoerceError> {
     use std::cmp::{Ord, Ordering};
     match &mut self {
       OwnedRowCell::Blob(data) => {
         let needed_len = match field.kind() {
           FieldKind::Blob(len) => len,
-          _ => return None,
+          _ => return Err(CoerceError::WrongKind),
         };
 
         let data_len = data.len() as u64;
         match data_len.cmp(needed_len) {
-          Ordering::Equal => Some(self),
-          Ordering::Greater => {
-            data.truncate(*needed_len as usize);
-            Some(self)
-          }
+          Ordering::Equal => Ok(self),
+          Ordering::Greater => Err(CoerceError::TooLong),
           Ordering::Less => {
             let padding_bytes = *needed_len - data_len;
             let mut padding = vec![0; padding_bytes as usize];
             data.append(&mut padding);
-            Some(self)
+            Ok(self)
           }
         }
       }
-      OwnedRowCell::Number { size, .. } => match field.kind() {
+      OwnedRowCell::Number { size, value } => match field.kind() {
         // it's all i64's under the hood...
         FieldKind::Number(schema_size) => {
+          // ...but the value still has to fit in the bytes we have for it
+          let bits = *schema_size as u32 * 8;
+          if bits < 64 && (*value < -(1 << (bits - 1)) || *value >= 1 << (bits - 1)) {
+            return Err(CoerceError::OutOfRange);
+          }
           *size = *schema_size;
-          Some(self)
+          Ok(self)
         }
-        _ => None,
+        _ => Err(CoerceError::WrongKind),
       },
-      OwnedRowCell::Str { max_size, .. } => {
-        // padding / truncating happens when we write the string
-        // which is convenient, because we only need to make the
-        // size args match
+      OwnedRowCell::Str { value, max_size } => {
+        // padding happens when we write the string
         let max_len = match field.kind() {
-          FieldKind::Str(len) => len,
-          _ => return None,
+          FieldKind::Str(len) => *len,
+          _ => return Err(CoerceError::WrongKind),
         };
-        *max_size = *max_len;
-        Some(self)
+        if value.len() as u64 > max_len {
+          return Err(CoerceError::TooLong);
+        }
+        *max_size = max_len;
+        Ok(self)
       }
+      // NULL fits in a column of any type
+      OwnedRowCell::Null => Ok(self),
     }
   }
 
@@ -255,6 +371,7 @@ impl OwnedRowCell {
         max_size: *max_size,
       },
       OwnedRowCell::Blob(data) => RowCell::Blob(data.as_ref()),
+      OwnedRowCell::Null => RowCell::Null,
     }
   }
   pub fn persist(&self, disk: &mut impl Write) -> io::Result<()> {
@@ -270,11 +387,10 @@ impl OwnedRowCell {
         let remaining_buf_size = *max_size as usize - value.len();
         disk.write_all(&vec![0; remaining_buf_size])?;
 
-        assert_eq!(
-          *max_size as usize,
-          value.as_bytes().len() + remaining_buf_size
-        );
+        assert_eq!(*max_size as usize, value.len() + remaining_buf_size);
       }
+      // NULLs are tracked by the row, so there's nothing to write
+      OwnedRowCell::Null => {}
     };
     Ok(())
   }
@@ -285,6 +401,28 @@ pub enum RowCell<'a> {
   Number { value: i64, size: u8 },
   Str { value: &'a str, max_size: u64 },
   Blob(&'a [u8]),
+  Null,
+}
+
+/// Why a value can't be stored in a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoerceError {
+  /// The column holds a different kind of value
+  WrongKind,
+  /// The number needs more bytes than the column has
+  OutOfRange,
+  /// The string or blob is longer than the column
+  TooLong,
+}
+
+impl Display for CoerceError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CoerceError::WrongKind => write!(f, "wrong type of value for column"),
+      CoerceError::OutOfRange => write!(f, "value out of range for column"),
+      CoerceError::TooLong => write!(f, "value too long for column"),
+    }
+  }
 }
 
 #[derive(Debug)]
@@ -333,7 +471,7 @@ impl<'a> RowCell<'a> {
         let slice = &slice[0..n as usize];
         let slice = &slice[0..len as usize];
         Ok(RowCell::Str {
-          value: &str::from_utf8(slice)?,
+          value: str::from_utf8(slice)?,
           max_size: n,
         })
       }
@@ -349,6 +487,51 @@ impl<'a> Display for RowCell<'a> {
       RowCell::Blob(data) => write!(f, "{}", hex::encode(data)),
       RowCell::Str { value, .. } => write!(f, "{}", value),
       RowCell::Number { value, .. } => write!(f, "{}", value),
+      RowCell::Null => write!(f, "NULL"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_row_meta_flags() -> Result<(), RowCellError> {
+    for &(is_last_row, is_deleted) in &[(false, false), (true, false), (false, true)] {
+      let mut disk = io::Cursor::new(vec![]);
+      RowMeta {
+        is_last_row,
+        is_deleted,
+      }
+      .persist(&mut disk)?;
+      disk.set_position(0);
+      let meta = RowMeta::from_persisted(&mut disk)?;
+      assert_eq!(meta.is_last_row, is_last_row);
+      assert_eq!(meta.is_deleted, is_deleted);
     }
+    // rows written before tombstones existed are still readable
+    let meta = RowMeta::from_persisted(&mut io::Cursor::new(vec![0, 1]))?;
+    assert!(meta.is_last_row && !meta.is_deleted);
+    Ok(())
+  }
+
+  #[test]
+  fn test_coerce_long_string() {
+    use crate::SchemaField;
+    let field = SchemaField::new(FieldKind::Str(4), "name".to_string()).unwrap();
+    let string = |value: &str| OwnedRowCell::Str {
+      value: value.to_string(),
+      max_size: 6,
+    };
+    assert_eq!(
+      string("hél").coerce_to(&field),
+      Ok(OwnedRowCell::Str {
+        value: "hél".to_string(),
+        max_size: 4,
+      })
+    );
+    // "é" is two bytes, so this is 5 bytes long
+    assert_eq!(string("hélo").coerce_to(&field), Err(CoerceError::TooLong));
   }
 }
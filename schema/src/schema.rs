@@ -150,7 +150,6 @@ impl OnDiskSchema {
 }
 
 #[cfg(test)]
-
 mod tests {
   use super::*;
   use crate::FieldKind;